@@ -1,9 +1,50 @@
 #version 450
 
 layout(location = 0) in vec4 inColor;
+
+layout(push_constant) uniform OutputTransform {
+    layout(offset = 80) uint colorSpace;
+} outputTransform;
+
 layout(location = 0) out vec4 outColor;
 
+const uint COLOR_SPACE_SDR = 0;
+const uint COLOR_SPACE_HDR10 = 1;
+const uint COLOR_SPACE_SCRGB = 2;
+
+// Brightness of SDR white on an HDR display, in nits
+const float PAPER_WHITE_NITS = 200.0;
+
+vec3 rec709ToRec2020(vec3 color) {
+    const mat3 conversion = mat3(
+        0.6274, 0.0691, 0.0164,
+        0.3293, 0.9195, 0.0880,
+        0.0433, 0.0114, 0.8956);
+    return conversion * color;
+}
+
+vec3 encodePQ(vec3 nits) {
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+    vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(m1));
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+vec3 applyOutputTransform(vec3 color) {
+    if (outputTransform.colorSpace == COLOR_SPACE_HDR10) {
+        return encodePQ(rec709ToRec2020(color) * PAPER_WHITE_NITS);
+    }
+    if (outputTransform.colorSpace == COLOR_SPACE_SCRGB) {
+        // scRGB is linear with 1.0 mapped to 80 nits
+        return color * (PAPER_WHITE_NITS / 80.0);
+    }
+    return color;
+}
+
 void main()
 {
-    outColor = inColor;
+    outColor = vec4(applyOutputTransform(inColor.rgb), inColor.a);
 }
\ No newline at end of file
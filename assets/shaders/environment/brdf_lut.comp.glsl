@@ -0,0 +1,71 @@
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+// The scale and bias applied to F0, indexed by N dot V along x and roughness along y
+layout(binding = 0, rg16f) uniform writeonly image2DArray lut;
+
+layout(push_constant) uniform BrdfLut {
+    uint sampleCount;
+} lutSettings;
+
+const float PI = 3.14159265359;
+
+vec2 hammersley(uint index, uint count) {
+    uint bits = index;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return vec2(float(index) / float(count), float(bits) * 2.3283064365386963e-10);
+}
+
+vec3 importanceSampleGgx(vec2 xi, float roughness) {
+    float alpha = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cosTheta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+    return vec3(cos(phi) * sinTheta, sin(phi) * sinTheta, cosTheta);
+}
+
+// Schlick-GGX with the remapping of k used for image based lighting
+float geometrySmith(float nDotV, float nDotL, float roughness) {
+    float k = roughness * roughness / 2.0;
+    float viewTerm = nDotV / (nDotV * (1.0 - k) + k);
+    float lightTerm = nDotL / (nDotL * (1.0 - k) + k);
+    return viewTerm * lightTerm;
+}
+
+void main() {
+    ivec2 id = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(lut).xy;
+    if (any(greaterThanEqual(id, size))) {
+        return;
+    }
+
+    vec2 uv = (vec2(id) + 0.5) / vec2(size);
+    float nDotV = uv.x;
+    float roughness = uv.y;
+    vec3 view = vec3(sqrt(1.0 - nDotV * nDotV), 0.0, nDotV);
+
+    float scale = 0.0;
+    float bias = 0.0;
+    for (uint index = 0u; index < lutSettings.sampleCount; ++index) {
+        vec3 halfway = importanceSampleGgx(hammersley(index, lutSettings.sampleCount), roughness);
+        vec3 light = normalize(2.0 * dot(view, halfway) * halfway - view);
+        float nDotL = max(light.z, 0.0);
+        if (nDotL <= 0.0) {
+            continue;
+        }
+        float nDotH = max(halfway.z, 0.0);
+        float vDotH = max(dot(view, halfway), 0.0);
+        float visibility = geometrySmith(nDotV, nDotL, roughness) * vDotH / (nDotH * nDotV);
+        float fresnel = pow(1.0 - vDotH, 5.0);
+        scale += (1.0 - fresnel) * visibility;
+        bias += fresnel * visibility;
+    }
+
+    vec2 result = vec2(scale, bias) / float(lutSettings.sampleCount);
+    imageStore(lut, ivec3(id, 0), vec4(result, 0.0, 1.0));
+}
@@ -0,0 +1,37 @@
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(binding = 0) uniform sampler2D equirectangular;
+
+layout(binding = 1, rgba16f) uniform writeonly image2DArray cubemap;
+
+const float PI = 3.14159265359;
+
+// Faces are ordered +X, -X, +Y, -Y, +Z, -Z, following the Vulkan cube map conventions
+vec3 cubeDirection(ivec3 texel, vec2 size) {
+    vec2 st = (vec2(texel.xy) + 0.5) / size * 2.0 - 1.0;
+    switch (texel.z) {
+        case 0: return normalize(vec3(1.0, -st.y, -st.x));
+        case 1: return normalize(vec3(-1.0, -st.y, st.x));
+        case 2: return normalize(vec3(st.x, 1.0, st.y));
+        case 3: return normalize(vec3(st.x, -1.0, -st.y));
+        case 4: return normalize(vec3(st.x, -st.y, 1.0));
+        default: return normalize(vec3(-st.x, -st.y, -1.0));
+    }
+}
+
+void main() {
+    ivec3 id = ivec3(gl_GlobalInvocationID);
+    ivec2 size = imageSize(cubemap).xy;
+    if (any(greaterThanEqual(id.xy, size))) {
+        return;
+    }
+
+    vec3 direction = cubeDirection(id, vec2(size));
+    vec2 uv = vec2(
+        atan(direction.z, direction.x) / (2.0 * PI) + 0.5,
+        acos(clamp(direction.y, -1.0, 1.0)) / PI);
+
+    imageStore(cubemap, id, vec4(textureLod(equirectangular, uv, 0.0).rgb, 1.0));
+}
@@ -0,0 +1,57 @@
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(binding = 0) uniform samplerCube environment;
+
+layout(binding = 1, rgba16f) uniform writeonly image2DArray irradiance;
+
+layout(push_constant) uniform Irradiance {
+    float sampleDelta;
+    // A blurrier level of the environment is sampled so that small, bright sources do not alias
+    float sourceLod;
+} irradianceSettings;
+
+const float PI = 3.14159265359;
+
+// Faces are ordered +X, -X, +Y, -Y, +Z, -Z, following the Vulkan cube map conventions
+vec3 cubeDirection(ivec3 texel, vec2 size) {
+    vec2 st = (vec2(texel.xy) + 0.5) / size * 2.0 - 1.0;
+    switch (texel.z) {
+        case 0: return normalize(vec3(1.0, -st.y, -st.x));
+        case 1: return normalize(vec3(-1.0, -st.y, st.x));
+        case 2: return normalize(vec3(st.x, 1.0, st.y));
+        case 3: return normalize(vec3(st.x, -1.0, -st.y));
+        case 4: return normalize(vec3(st.x, -st.y, 1.0));
+        default: return normalize(vec3(-st.x, -st.y, -1.0));
+    }
+}
+
+void main() {
+    ivec3 id = ivec3(gl_GlobalInvocationID);
+    ivec2 size = imageSize(irradiance).xy;
+    if (any(greaterThanEqual(id.xy, size))) {
+        return;
+    }
+
+    vec3 normal = cubeDirection(id, vec2(size));
+    vec3 up = abs(normal.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(0.0, 0.0, 1.0);
+    vec3 right = normalize(cross(up, normal));
+    up = cross(normal, right);
+
+    // Cosine weighted convolution over the hemisphere around the normal
+    vec3 sum = vec3(0.0);
+    float sampleCount = 0.0;
+    float delta = irradianceSettings.sampleDelta;
+    for (float phi = 0.0; phi < 2.0 * PI; phi += delta) {
+        for (float theta = 0.0; theta < 0.5 * PI; theta += delta) {
+            vec3 tangentSample = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            vec3 direction = tangentSample.x * right + tangentSample.y * up + tangentSample.z * normal;
+            vec3 radiance = textureLod(environment, direction, irradianceSettings.sourceLod).rgb;
+            sum += radiance * cos(theta) * sin(theta);
+            sampleCount += 1.0;
+        }
+    }
+
+    imageStore(irradiance, id, vec4(PI * sum / sampleCount, 1.0));
+}
@@ -0,0 +1,100 @@
+#version 450
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(binding = 0) uniform samplerCube environment;
+
+// A single mip level of the prefiltered cubemap
+layout(binding = 1, rgba16f) uniform writeonly image2DArray prefiltered;
+
+layout(push_constant) uniform Prefilter {
+    float roughness;
+    uint sampleCount;
+    // Width of a face of the environment's base level
+    float environmentSize;
+} prefilterSettings;
+
+const float PI = 3.14159265359;
+
+// Faces are ordered +X, -X, +Y, -Y, +Z, -Z, following the Vulkan cube map conventions
+vec3 cubeDirection(ivec3 texel, vec2 size) {
+    vec2 st = (vec2(texel.xy) + 0.5) / size * 2.0 - 1.0;
+    switch (texel.z) {
+        case 0: return normalize(vec3(1.0, -st.y, -st.x));
+        case 1: return normalize(vec3(-1.0, -st.y, st.x));
+        case 2: return normalize(vec3(st.x, 1.0, st.y));
+        case 3: return normalize(vec3(st.x, -1.0, -st.y));
+        case 4: return normalize(vec3(st.x, -st.y, 1.0));
+        default: return normalize(vec3(-st.x, -st.y, -1.0));
+    }
+}
+
+vec2 hammersley(uint index, uint count) {
+    uint bits = index;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return vec2(float(index) / float(count), float(bits) * 2.3283064365386963e-10);
+}
+
+vec3 importanceSampleGgx(vec2 xi, vec3 normal, float roughness) {
+    float alpha = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cosTheta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+    vec3 halfway = vec3(cos(phi) * sinTheta, sin(phi) * sinTheta, cosTheta);
+
+    vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, normal));
+    vec3 bitangent = cross(normal, tangent);
+    return normalize(tangent * halfway.x + bitangent * halfway.y + normal * halfway.z);
+}
+
+float distributionGgx(float nDotH, float roughness) {
+    float alpha = roughness * roughness;
+    float alphaSquared = alpha * alpha;
+    float denominator = nDotH * nDotH * (alphaSquared - 1.0) + 1.0;
+    return alphaSquared / (PI * denominator * denominator);
+}
+
+void main() {
+    ivec3 id = ivec3(gl_GlobalInvocationID);
+    ivec2 size = imageSize(prefiltered).xy;
+    if (any(greaterThanEqual(id.xy, size))) {
+        return;
+    }
+
+    // The view direction is assumed to match the normal, as in the split sum approximation
+    vec3 normal = cubeDirection(id, vec2(size));
+    float roughness = prefilterSettings.roughness;
+    if (roughness == 0.0) {
+        imageStore(prefiltered, id, vec4(textureLod(environment, normal, 0.0).rgb, 1.0));
+        return;
+    }
+
+    float texelSolidAngle = 4.0 * PI / (6.0 * prefilterSettings.environmentSize * prefilterSettings.environmentSize);
+    vec3 sum = vec3(0.0);
+    float totalWeight = 0.0;
+    for (uint index = 0u; index < prefilterSettings.sampleCount; ++index) {
+        vec2 xi = hammersley(index, prefilterSettings.sampleCount);
+        vec3 halfway = importanceSampleGgx(xi, normal, roughness);
+        vec3 light = normalize(2.0 * dot(normal, halfway) * halfway - normal);
+        float nDotL = dot(normal, light);
+        if (nDotL <= 0.0) {
+            continue;
+        }
+
+        // Samples with a low probability cover a larger solid angle, so they read a blurrier level
+        float nDotH = max(dot(normal, halfway), 0.0);
+        float pdf = distributionGgx(nDotH, roughness) * 0.25 + 0.0001;
+        float sampleSolidAngle = 1.0 / (float(prefilterSettings.sampleCount) * pdf + 0.0001);
+        float lod = max(0.5 * log2(sampleSolidAngle / texelSolidAngle) + 1.0, 0.0);
+
+        sum += textureLod(environment, light, lod).rgb * nDotL;
+        totalWeight += nDotL;
+    }
+
+    imageStore(prefiltered, id, vec4(sum / max(totalWeight, 0.0001), 1.0));
+}
@@ -0,0 +1,110 @@
+#version 450
+#extension GL_EXT_samplerless_texture_functions : require
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+// Sampled through a view of the image's own format, so sRGB texels arrive linear
+layout(binding = 0) uniform texture2DArray source;
+
+// Written through a view without sRGB encoding, which is applied here instead
+layout(binding = 1) uniform writeonly image2DArray destination;
+
+layout(push_constant) uniform Downsample {
+    uint filterMode;
+    uint encodeSrgb;
+    uint clampNegative;
+} downsample;
+
+const uint FILTER_BOX = 0;
+const uint FILTER_KAISER = 1;
+
+// Kaiser windowed sinc reaching three source texels to either side
+const int KAISER_RADIUS = 3;
+const float KAISER_ALPHA = 4.0;
+const float PI = 3.14159265359;
+
+vec4 fetch(ivec2 texel, int layer) {
+    ivec2 size = textureSize(source, 0).xy;
+    return texelFetch(source, ivec3(clamp(texel, ivec2(0), size - 1), layer), 0);
+}
+
+vec4 boxFilter(ivec2 texel, int layer) {
+    ivec2 base = texel * 2;
+    return 0.25 * (fetch(base, layer) +
+                   fetch(base + ivec2(1, 0), layer) +
+                   fetch(base + ivec2(0, 1), layer) +
+                   fetch(base + ivec2(1, 1), layer));
+}
+
+float besselI0(float x) {
+    float sum = 1.0;
+    float term = 1.0;
+    for (int k = 1; k < 16; ++k) {
+        term *= (x * x) / (4.0 * float(k * k));
+        sum += term;
+    }
+    return sum;
+}
+
+float sinc(float x) {
+    if (abs(x) < 1e-5) {
+        return 1.0;
+    }
+    return sin(PI * x) / (PI * x);
+}
+
+// Distance is measured in destination texels, where the window spans half the source radius
+float kaiserWeight(float distance) {
+    float window = float(KAISER_RADIUS) * 0.5;
+    float ratio = distance / window;
+    if (abs(ratio) >= 1.0) {
+        return 0.0;
+    }
+    return sinc(distance) * besselI0(KAISER_ALPHA * sqrt(1.0 - ratio * ratio)) / besselI0(KAISER_ALPHA);
+}
+
+vec4 kaiserFilter(ivec2 texel, int layer) {
+    vec2 center = (vec2(texel) + 0.5) * 2.0;
+    ivec2 base = texel * 2;
+    vec4 sum = vec4(0.0);
+    float weightSum = 0.0;
+    for (int y = 1 - KAISER_RADIUS; y <= KAISER_RADIUS; ++y) {
+        float weightY = kaiserWeight((float(base.y + y) + 0.5 - center.y) * 0.5);
+        for (int x = 1 - KAISER_RADIUS; x <= KAISER_RADIUS; ++x) {
+            float weightX = kaiserWeight((float(base.x + x) + 0.5 - center.x) * 0.5);
+            float weight = weightX * weightY;
+            sum += fetch(base + ivec2(x, y), layer) * weight;
+            weightSum += weight;
+        }
+    }
+    return sum / weightSum;
+}
+
+vec3 linearToSrgb(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+}
+
+void main() {
+    ivec3 id = ivec3(gl_GlobalInvocationID);
+    if (any(greaterThanEqual(id.xy, imageSize(destination).xy))) {
+        return;
+    }
+
+    vec4 color = downsample.filterMode == FILTER_KAISER
+        ? kaiserFilter(id.xy, id.z)
+        : boxFilter(id.xy, id.z);
+
+    // Negative lobes can ring below zero around hard edges,
+    // which only formats that cannot hold negative values clamp away
+    if (downsample.clampNegative != 0) {
+        color = max(color, vec4(0.0));
+    }
+
+    if (downsample.encodeSrgb != 0) {
+        color.rgb = linearToSrgb(clamp(color.rgb, 0.0, 1.0));
+    }
+
+    imageStore(destination, id, color);
+}
@@ -1,6 +1,7 @@
 use anyhow::Result;
 use ash::vk;
 use nalgebra_glm as glm;
+use std::time::{SystemTime, UNIX_EPOCH};
 use support::{
     camera::{update_free_camera, CameraDirection, FreeCamera},
     run_app,
@@ -9,7 +10,7 @@ use support::{
     },
     App, ApplicationState,
 };
-use winit::event::VirtualKeyCode;
+use winit::event::{ElementState, VirtualKeyCode};
 
 #[derive(Default)]
 struct DemoApp {
@@ -18,6 +19,7 @@ struct DemoApp {
     cube: Option<CubeRender>,
     angle: f32,
     camera: FreeCamera,
+    screenshot_requested: bool,
 }
 
 impl App for DemoApp {
@@ -27,10 +29,7 @@ impl App for DemoApp {
 
         self.rendergraph = create_rendergraph(&render_device)?;
 
-        let cube = Cube::new(
-            render_device.context.allocator.clone(),
-            &render_device.command_pool,
-        )?;
+        let cube = Cube::new(&render_device.context)?;
         let mut cube_render = CubeRender::new(render_device.context.device.clone(), cube);
 
         cube_render.create_pipeline(
@@ -43,6 +42,28 @@ impl App for DemoApp {
         Ok(())
     }
 
+    fn on_resize(&mut self, extent: vk::Extent2D, render_device: &RenderDevice) -> Result<()> {
+        if extent.width == 0 || extent.height == 0 {
+            return Ok(());
+        }
+        self.rendergraph = create_rendergraph(render_device)?;
+        if let Some(cube) = self.cube.as_mut() {
+            cube.create_pipeline(
+                &mut self.shader_cache,
+                self.rendergraph.pass("offscreen")?.render_pass.clone(),
+                vk::SampleCountFlags::TYPE_1,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn on_key(&mut self, keystate: ElementState, keycode: VirtualKeyCode) -> Result<()> {
+        if (keycode, keystate) == (VirtualKeyCode::F12, ElementState::Pressed) {
+            self.screenshot_requested = true;
+        }
+        Ok(())
+    }
+
     fn update(&mut self, state: &ApplicationState) -> Result<()> {
         self.angle += 10.0 * state.system.delta_time as f32;
         update_free_camera(&mut self.camera, state)?;
@@ -65,6 +86,18 @@ impl App for DemoApp {
 
         let logical_size = state.window.inner_size();
         let window_dimensions = [logical_size.width, logical_size.height];
+        if self.screenshot_requested {
+            self.screenshot_requested = false;
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            render_device
+                .frame
+                .capture_next(format!("screenshot-{}", timestamp))?;
+        }
+
+        let output_color_space = render_device
+            .frame
+            .swapchain_properties
+            .output_color_space();
         let device = render_device.context.device.clone();
         render_device
             .frame
@@ -81,6 +114,7 @@ impl App for DemoApp {
                                 mvp,
                                 glm::vec4(1.0, 1.0, 1.0, 1.0),
                                 false,
+                                output_color_space,
                             )?;
                         }
                         Ok(())
@@ -90,17 +124,6 @@ impl App for DemoApp {
                 Ok(())
             })?;
 
-        if render_device.frame.recreated_swapchain {
-            self.rendergraph = create_rendergraph(render_device)?;
-            if let Some(cube) = self.cube.as_mut() {
-                cube.create_pipeline(
-                    &mut self.shader_cache,
-                    self.rendergraph.pass("offscreen")?.render_pass.clone(),
-                    vk::SampleCountFlags::TYPE_1,
-                )?;
-            }
-        }
-
         Ok(())
     }
 }
@@ -1,6 +1,9 @@
-use crate::{vulkan::RenderDevice, Input, System};
+use crate::{
+    vulkan::{FrameConfig, RenderDevice, StagingConfig, SwapchainConfig},
+    Input, System,
+};
 use anyhow::{Context, Result};
-use ash::version::DeviceV1_0;
+use ash::{version::DeviceV1_0, vk};
 use simplelog::{CombinedLogger, Config, LevelFilter, TermLogger, TerminalMode, WriteLogger};
 use std::fs::File;
 use winit::{
@@ -22,6 +25,18 @@ pub trait App {
         Ok(())
     }
 
+    fn swapchain_config(&self) -> SwapchainConfig {
+        SwapchainConfig::default()
+    }
+
+    fn staging_config(&self) -> StagingConfig {
+        StagingConfig::default()
+    }
+
+    fn frame_config(&self) -> FrameConfig {
+        FrameConfig::default()
+    }
+
     fn initialize(&mut self, _: &ApplicationState, _: &RenderDevice) -> Result<()> {
         Ok(())
     }
@@ -30,6 +45,11 @@ pub trait App {
         Ok(())
     }
 
+    /// Called after the swapchain is recreated, and with a zero extent when the window is minimized
+    fn on_resize(&mut self, _extent: vk::Extent2D, _: &RenderDevice) -> Result<()> {
+        Ok(())
+    }
+
     fn update(&mut self, _: &ApplicationState) -> Result<()> {
         Ok(())
     }
@@ -87,12 +107,20 @@ pub fn run_app(mut app: impl App + 'static, title: &str) -> Result<()> {
 
     let logical_size = window.inner_size();
     let window_dimensions = [logical_size.width, logical_size.height];
-    let mut render_device = RenderDevice::new(&window, &window_dimensions)?;
+    let mut render_device = RenderDevice::new(
+        &window,
+        &window_dimensions,
+        app.swapchain_config(),
+        app.staging_config(),
+        app.frame_config(),
+    )?;
 
     let mut application_state = ApplicationState::new(window, window_dimensions);
 
     app.initialize(&application_state, &render_device)?;
 
+    let mut minimized = false;
+
     event_loop.run(move |event, _, control_flow| {
         let result = || -> Result<()> {
             *control_flow = ControlFlow::Poll;
@@ -115,6 +143,20 @@ pub fn run_app(mut app: impl App + 'static, title: &str) -> Result<()> {
                 Event::MainEventsCleared => {
                     app.update(&application_state)?;
                     app.render(&application_state, &mut render_device)?;
+                    if render_device.frame.recreated_swapchain {
+                        minimized = false;
+                        let extent = render_device.frame.swapchain_properties.extent;
+                        app.on_resize(extent, &render_device)?;
+                    }
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::Resized(size),
+                    ..
+                } => {
+                    if (size.width == 0 || size.height == 0) && !minimized {
+                        minimized = true;
+                        app.on_resize(vk::Extent2D::default(), &render_device)?;
+                    }
                 }
                 Event::WindowEvent {
                     event:
@@ -139,7 +181,7 @@ pub fn run_app(mut app: impl App + 'static, title: &str) -> Result<()> {
                 }
                 Event::LoopDestroyed => {
                     app.cleanup(&render_device)?;
-                    unsafe { render_device.context.device.handle.device_wait_idle()? };
+                    render_device.context.device.wait_idle()?;
                 }
                 _ => {}
             }
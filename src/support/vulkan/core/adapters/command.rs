@@ -40,80 +40,54 @@ impl CommandPool {
         Ok(command_buffers)
     }
 
+    /// Returns every command buffer allocated from this pool to the initial state
+    pub fn reset(&self) -> Result<()> {
+        unsafe {
+            self.device
+                .handle
+                .reset_command_pool(self.handle, vk::CommandPoolResetFlags::empty())
+        }?;
+        Ok(())
+    }
+
     pub fn copy_buffer_to_buffer(&self, info: &BufferToBufferCopy) -> Result<()> {
-        let device = self.device.handle.clone();
         self.execute_once(|command_buffer| {
-            unsafe {
-                device.cmd_copy_buffer(command_buffer, info.source, info.destination, &info.regions)
-            };
+            self.device.copy_buffer_to_buffer(command_buffer, info);
             Ok(())
         })
     }
 
     pub fn copy_buffer_to_image(&self, info: &BufferToImageCopy) -> Result<()> {
-        let device = self.device.handle.clone();
         self.execute_once(|command_buffer| {
-            unsafe {
-                device.cmd_copy_buffer_to_image(
-                    command_buffer,
-                    info.source,
-                    info.destination,
-                    info.dst_image_layout,
-                    &info.regions,
-                )
-            };
+            self.device.copy_buffer_to_image(command_buffer, info);
+            Ok(())
+        })
+    }
+
+    pub fn copy_image_to_buffer(&self, info: &ImageToBufferCopy) -> Result<()> {
+        self.execute_once(|command_buffer| {
+            self.device.copy_image_to_buffer(command_buffer, info);
             Ok(())
         })
     }
 
     pub fn copy_image_to_image(&self, info: &ImageToImageCopy) -> Result<()> {
-        let device = self.device.handle.clone();
         self.execute_once(|command_buffer| {
-            unsafe {
-                device.cmd_copy_image(
-                    command_buffer,
-                    info.source,
-                    info.source_layout,
-                    info.destination,
-                    info.destination_layout,
-                    &info.regions,
-                )
-            };
+            self.device.copy_image_to_image(command_buffer, info);
             Ok(())
         })
     }
 
     pub fn transition_image_layout(&self, info: &PipelineBarrier) -> Result<()> {
-        let device = self.device.handle.clone();
         self.execute_once(|command_buffer| {
-            unsafe {
-                device.cmd_pipeline_barrier(
-                    command_buffer,
-                    info.src_stage_mask,
-                    info.dst_stage_mask,
-                    info.dependency_flags,
-                    &info.memory_barriers,
-                    &info.buffer_memory_barriers,
-                    &info.image_memory_barriers,
-                )
-            };
+            self.device.pipeline_barrier(command_buffer, info);
             Ok(())
         })
     }
 
     pub fn blit_image(&self, info: &BlitImage) -> Result<()> {
         self.execute_once(|command_buffer| {
-            unsafe {
-                self.device.handle.cmd_blit_image(
-                    command_buffer,
-                    info.src_image,
-                    info.src_image_layout,
-                    info.dst_image,
-                    info.dst_image_layout,
-                    &info.regions,
-                    info.filter,
-                )
-            }
+            self.device.blit_image(command_buffer, info);
             Ok(())
         })
     }
@@ -140,13 +114,13 @@ impl CommandPool {
 
         let device = self.device.handle.clone();
         unsafe {
-            device.queue_submit(self.queue, &submit_info_arr, fence.handle)?;
+            self.device
+                .queue_submit(self.queue, &submit_info_arr, fence.handle)?;
             device.wait_for_fences(
                 &[fence.handle],
                 true,
                 std::time::Duration::from_secs(100).as_nanos() as _,
             )?;
-            device.queue_wait_idle(self.queue)?;
             device.free_command_buffers(self.handle, &command_buffers);
         }
 
@@ -178,6 +152,15 @@ pub struct BufferToImageCopy {
     pub dst_image_layout: vk::ImageLayout,
 }
 
+#[derive(Builder)]
+pub struct ImageToBufferCopy {
+    pub source: vk::Image,
+    #[builder(default = "vk::ImageLayout::TRANSFER_SRC_OPTIMAL")]
+    pub src_image_layout: vk::ImageLayout,
+    pub destination: vk::Buffer,
+    pub regions: Vec<vk::BufferImageCopy>,
+}
+
 #[derive(Builder)]
 pub struct ImageToImageCopy {
     pub source: vk::Image,
@@ -25,11 +25,10 @@ impl DescriptorSetLayout {
 
 impl Drop for DescriptorSetLayout {
     fn drop(&mut self) {
-        unsafe {
-            self.device
-                .handle
-                .destroy_descriptor_set_layout(self.handle, None);
-        }
+        let handle = self.handle;
+        self.device.defer_destruction(move |device| unsafe {
+            device.destroy_descriptor_set_layout(handle, None)
+        });
     }
 }
 
@@ -69,10 +68,9 @@ impl DescriptorPool {
 
 impl Drop for DescriptorPool {
     fn drop(&mut self) {
-        unsafe {
-            self.device
-                .handle
-                .destroy_descriptor_pool(self.handle, None);
-        }
+        let handle = self.handle;
+        self.device.defer_destruction(move |device| unsafe {
+            device.destroy_descriptor_pool(handle, None)
+        });
     }
 }
@@ -35,7 +35,6 @@ impl Pipeline {
         Ok(pipeline)
     }
 
-    #[allow(dead_code)]
     pub fn new_compute(
         device: Arc<Device>,
         create_info: vk::ComputePipelineCreateInfoBuilder,
@@ -69,9 +68,9 @@ impl Pipeline {
 
 impl Drop for Pipeline {
     fn drop(&mut self) {
-        unsafe {
-            self.device.handle.destroy_pipeline(self.handle, None);
-        }
+        let handle = self.handle;
+        self.device
+            .defer_destruction(move |device| unsafe { device.destroy_pipeline(handle, None) });
     }
 }
 
@@ -90,11 +89,10 @@ impl PipelineLayout {
 
 impl Drop for PipelineLayout {
     fn drop(&mut self) {
-        unsafe {
-            self.device
-                .handle
-                .destroy_pipeline_layout(self.handle, None);
-        }
+        let handle = self.handle;
+        self.device.defer_destruction(move |device| unsafe {
+            device.destroy_pipeline_layout(handle, None)
+        });
     }
 }
 
@@ -19,14 +19,46 @@ impl RenderPass {
         &self,
         buffer: vk::CommandBuffer,
         begin_info: vk::RenderPassBeginInfoBuilder,
+        action: impl FnMut(vk::CommandBuffer) -> Result<()>,
+    ) -> Result<()> {
+        self.record_with_contents(buffer, begin_info, vk::SubpassContents::INLINE, action)
+    }
+
+    /// Begins the render pass and executes previously recorded secondary command buffers in it
+    pub fn execute_secondary(
+        &self,
+        buffer: vk::CommandBuffer,
+        begin_info: vk::RenderPassBeginInfoBuilder,
+        secondary_command_buffers: &[vk::CommandBuffer],
+    ) -> Result<()> {
+        self.record_with_contents(
+            buffer,
+            begin_info,
+            vk::SubpassContents::SECONDARY_COMMAND_BUFFERS,
+            |buffer| {
+                if !secondary_command_buffers.is_empty() {
+                    unsafe {
+                        self.device
+                            .handle
+                            .cmd_execute_commands(buffer, secondary_command_buffers)
+                    };
+                }
+                Ok(())
+            },
+        )
+    }
+
+    pub fn record_with_contents(
+        &self,
+        buffer: vk::CommandBuffer,
+        begin_info: vk::RenderPassBeginInfoBuilder,
+        contents: vk::SubpassContents,
         mut action: impl FnMut(vk::CommandBuffer) -> Result<()>,
     ) -> Result<()> {
         unsafe {
-            self.device.handle.cmd_begin_render_pass(
-                buffer,
-                &begin_info,
-                vk::SubpassContents::INLINE,
-            )
+            self.device
+                .handle
+                .cmd_begin_render_pass(buffer, &begin_info, contents)
         };
 
         action(buffer)?;
@@ -41,9 +73,9 @@ impl RenderPass {
 
 impl Drop for RenderPass {
     fn drop(&mut self) {
-        unsafe {
-            self.device.handle.destroy_render_pass(self.handle, None);
-        }
+        let handle = self.handle;
+        self.device
+            .defer_destruction(move |device| unsafe { device.destroy_render_pass(handle, None) });
     }
 }
 
@@ -62,8 +94,8 @@ impl Framebuffer {
 
 impl Drop for Framebuffer {
     fn drop(&mut self) {
-        unsafe {
-            self.device.handle.destroy_framebuffer(self.handle, None);
-        }
+        let handle = self.handle;
+        self.device
+            .defer_destruction(move |device| unsafe { device.destroy_framebuffer(handle, None) });
     }
 }
@@ -1,6 +1,9 @@
 use crate::vulkan::core::Device;
 use anyhow::Result;
-use ash::{version::DeviceV1_0, vk};
+use ash::{
+    version::{DeviceV1_0, DeviceV1_2},
+    vk,
+};
 use std::sync::Arc;
 
 pub struct Fence {
@@ -42,3 +45,49 @@ impl Drop for Semaphore {
         unsafe { self.device.handle.destroy_semaphore(self.handle, None) }
     }
 }
+
+pub struct TimelineSemaphore {
+    pub handle: vk::Semaphore,
+    device: Arc<Device>,
+}
+
+impl TimelineSemaphore {
+    pub fn new(device: Arc<Device>, initial_value: u64) -> Result<Self> {
+        let mut type_create_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(initial_value);
+        let create_info = vk::SemaphoreCreateInfo::builder().push_next(&mut type_create_info);
+        let handle = unsafe { device.handle.create_semaphore(&create_info, None) }?;
+        let semaphore = Self { handle, device };
+        Ok(semaphore)
+    }
+
+    pub fn value(&self) -> Result<u64> {
+        let value = unsafe { self.device.handle.get_semaphore_counter_value(self.handle) }?;
+        Ok(value)
+    }
+
+    pub fn wait(&self, value: u64, timeout: u64) -> Result<()> {
+        let semaphores = [self.handle];
+        let values = [value];
+        let wait_info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(&semaphores)
+            .values(&values);
+        unsafe { self.device.handle.wait_semaphores(&wait_info, timeout) }?;
+        Ok(())
+    }
+
+    pub fn signal(&self, value: u64) -> Result<()> {
+        let signal_info = vk::SemaphoreSignalInfo::builder()
+            .semaphore(self.handle)
+            .value(value);
+        unsafe { self.device.handle.signal_semaphore(&signal_info) }?;
+        Ok(())
+    }
+}
+
+impl Drop for TimelineSemaphore {
+    fn drop(&mut self) {
+        unsafe { self.device.handle.destroy_semaphore(self.handle, None) }
+    }
+}
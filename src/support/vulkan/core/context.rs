@@ -1,25 +1,35 @@
-pub use self::{debug::*, device::*, instance::*, physical_device::*};
+pub use self::{debug::*, device::*, instance::*, memory::*, physical_device::*};
 
 mod debug;
 mod device;
 mod instance;
+mod memory;
 mod physical_device;
 
+use crate::vulkan::core::{HdrMetadata, MipmapGenerator, StagingConfig, StagingRing};
 use anyhow::{ensure, Context as AnyhowContext, Result};
 use ash::{
     extensions::khr::{Surface as AshSurface, Swapchain},
-    version::{DeviceV1_0, InstanceV1_0},
+    version::{DeviceV1_0, EntryV1_0, InstanceV1_0},
     vk::{self, SurfaceKHR},
 };
 use ash_window::{create_surface, enumerate_required_extensions};
 use raw_window_handle::HasRawWindowHandle;
-use std::{os::raw::c_char, sync::Arc};
-use vk_mem::{Allocator, AllocatorCreateInfo};
+use std::{
+    ffi::CStr,
+    os::raw::c_char,
+    sync::{Arc, Mutex},
+};
+use vk_mem::{Allocator, AllocatorCreateFlags, AllocatorCreateInfo};
 
 // The order the struct members are declared in
 // determines the order they are 'Drop'ped in
 // when this struct is dropped
 pub struct Context {
+    mipmap_generator: Mutex<Option<Arc<MipmapGenerator>>>,
+    pub staging: Arc<StagingRing>,
+    pub enabled_features: EnabledFeatures,
+    hdr_metadata_fn: Option<vk::ExtHdrMetadataFn>,
     pub debug: Option<VulkanDebug>,
     pub allocator: Arc<vk_mem::Allocator>,
     pub device: Arc<Device>,
@@ -30,21 +40,55 @@ pub struct Context {
 }
 
 impl Context {
-    pub fn new(window_handle: &impl HasRawWindowHandle) -> Result<Self> {
-        let instance_extensions = Self::instance_extensions(window_handle)?;
+    pub fn new(
+        window_handle: &impl HasRawWindowHandle,
+        staging_config: &StagingConfig,
+    ) -> Result<Self> {
+        let entry = unsafe { ash::Entry::new()? };
+
+        let mut enabled_features = EnabledFeatures::default();
+        let available_instance_extensions = entry.enumerate_instance_extension_properties()?;
+        enabled_features.swapchain_colorspace = extension_available(
+            &available_instance_extensions,
+            vk::ExtSwapchainColorspaceFn::name(),
+        );
+
+        let instance_extensions = Self::instance_extensions(window_handle, &enabled_features)?;
         let layers = Self::layers()?;
-        let device_extensions = Self::device_extensions();
-        let features = Self::features();
 
-        let entry = unsafe { ash::Entry::new()? };
         let instance = Instance::new(&entry, &instance_extensions, &layers)?;
         let surface = Surface::new(&entry, &instance.handle, window_handle)?;
         let physical_device = PhysicalDevice::new(&instance.handle, &surface)?;
 
+        enabled_features.hdr_metadata =
+            physical_device.supports_extension(&instance.handle, vk::ExtHdrMetadataFn::name())?;
+        enabled_features.memory_budget =
+            physical_device.supports_extension(&instance.handle, vk::ExtMemoryBudgetFn::name())?;
+        let vulkan12_supported = physical_device.supports_vulkan12(&instance.handle);
+        let supported_vulkan12_features = physical_device.vulkan12_features(&instance.handle);
+        enabled_features.timeline_semaphore =
+            supported_vulkan12_features.timeline_semaphore == vk::TRUE;
+        enabled_features.buffer_device_address =
+            supported_vulkan12_features.buffer_device_address == vk::TRUE;
+        let supported_features = physical_device.features(&instance.handle);
+        enabled_features.texture_compression_bc =
+            supported_features.texture_compression_bc == vk::TRUE;
+        enabled_features.texture_compression_etc2 =
+            supported_features.texture_compression_etc2 == vk::TRUE;
+        enabled_features.texture_compression_astc_ldr =
+            supported_features.texture_compression_astc_ldr == vk::TRUE;
+        enabled_features.storage_image_write_without_format =
+            supported_features.shader_storage_image_write_without_format == vk::TRUE;
+        let features = Self::features(&enabled_features);
+        let device_extensions = Self::device_extensions(&enabled_features);
+        let mut vulkan12_features = Self::vulkan12_features(&enabled_features);
+
         let mut queue_indices = vec![
             physical_device.graphics_queue_family_index,
             physical_device.presentation_queue_family_index,
         ];
+        queue_indices.extend(physical_device.transfer_queue_family_index);
+        queue_indices.sort_unstable();
         queue_indices.dedup();
         let queue_create_info_list = queue_indices
             .iter()
@@ -60,16 +104,37 @@ impl Context {
         // has been deprecated as of Vulkan 1.1, but the spec recommends stil
         // passing the layer name pointers here to maintain backwards compatibility
         // with older implementations.
-        let create_info = vk::DeviceCreateInfo::builder()
+        let mut create_info = vk::DeviceCreateInfo::builder()
             .queue_create_infos(queue_create_info_list.as_slice())
             .enabled_extension_names(&device_extensions)
             .enabled_features(&features)
             .enabled_layer_names(&layers);
+        if vulkan12_supported {
+            create_info = create_info.push_next(&mut vulkan12_features);
+        }
 
         let device = Device::new(&instance.handle, physical_device.handle, create_info)?;
         let device = Arc::new(device);
 
+        let hdr_metadata_fn = if enabled_features.hdr_metadata {
+            Some(vk::ExtHdrMetadataFn::load(|name| unsafe {
+                std::mem::transmute(
+                    instance
+                        .handle
+                        .get_device_proc_addr(device.handle.handle(), name.as_ptr()),
+                )
+            }))
+        } else {
+            None
+        };
+
+        let allocator_flags = if enabled_features.buffer_device_address {
+            AllocatorCreateFlags::BUFFER_DEVICE_ADDRESS
+        } else {
+            AllocatorCreateFlags::NONE
+        };
         let allocator_create_info = AllocatorCreateInfo {
+            flags: allocator_flags,
             device: device.handle.clone(),
             instance: instance.handle.clone(),
             physical_device: physical_device.handle,
@@ -84,7 +149,17 @@ impl Context {
             None
         };
 
+        let staging = Arc::new(StagingRing::new(
+            device.clone(),
+            allocator.clone(),
+            staging_config,
+        )?);
+
         Ok(Self {
+            mipmap_generator: Mutex::new(None),
+            staging,
+            enabled_features,
+            hdr_metadata_fn,
             debug,
             allocator,
             device,
@@ -95,7 +170,10 @@ impl Context {
         })
     }
 
-    fn instance_extensions(window_handle: &impl HasRawWindowHandle) -> Result<Vec<*const i8>> {
+    fn instance_extensions(
+        window_handle: &impl HasRawWindowHandle,
+        enabled_features: &EnabledFeatures,
+    ) -> Result<Vec<*const i8>> {
         let mut extensions: Vec<*const i8> = enumerate_required_extensions(window_handle)?
             .iter()
             .map(|extension| extension.as_ptr())
@@ -103,6 +181,9 @@ impl Context {
         if VulkanDebug::enabled() {
             extensions.push(VulkanDebug::extension_name().as_ptr());
         }
+        if enabled_features.swapchain_colorspace {
+            extensions.push(vk::ExtSwapchainColorspaceFn::name().as_ptr());
+        }
         Ok(extensions)
     }
 
@@ -114,16 +195,37 @@ impl Context {
         Ok(layers)
     }
 
-    fn device_extensions() -> Vec<*const c_char> {
-        vec![Swapchain::name().as_ptr()]
+    fn device_extensions(enabled_features: &EnabledFeatures) -> Vec<*const c_char> {
+        let mut extensions = vec![Swapchain::name().as_ptr()];
+        if enabled_features.hdr_metadata {
+            extensions.push(vk::ExtHdrMetadataFn::name().as_ptr());
+        }
+        if enabled_features.memory_budget {
+            extensions.push(vk::ExtMemoryBudgetFn::name().as_ptr());
+        }
+        extensions
     }
 
-    fn features<'a>() -> vk::PhysicalDeviceFeaturesBuilder<'a> {
+    fn features<'a>(enabled_features: &EnabledFeatures) -> vk::PhysicalDeviceFeaturesBuilder<'a> {
         vk::PhysicalDeviceFeatures::builder()
             .sample_rate_shading(true)
             .sampler_anisotropy(true)
             .fill_mode_non_solid(true)
             .wide_lines(true)
+            .texture_compression_bc(enabled_features.texture_compression_bc)
+            .texture_compression_etc2(enabled_features.texture_compression_etc2)
+            .texture_compression_astc_ldr(enabled_features.texture_compression_astc_ldr)
+            .shader_storage_image_write_without_format(
+                enabled_features.storage_image_write_without_format,
+            )
+    }
+
+    fn vulkan12_features<'a>(
+        enabled_features: &EnabledFeatures,
+    ) -> vk::PhysicalDeviceVulkan12FeaturesBuilder<'a> {
+        vk::PhysicalDeviceVulkan12Features::builder()
+            .timeline_semaphore(enabled_features.timeline_semaphore)
+            .buffer_device_address(enabled_features.buffer_device_address)
     }
 
     pub fn debug(&self) -> Result<&VulkanDebug> {
@@ -149,6 +251,27 @@ impl Context {
         Ok(capabilities)
     }
 
+    pub fn set_hdr_metadata(
+        &self,
+        swapchain: vk::SwapchainKHR,
+        metadata: &HdrMetadata,
+    ) -> Result<()> {
+        let hdr_metadata_fn = self.hdr_metadata_fn.as_ref().context(
+            "HDR metadata was set on a device that does not support VK_EXT_hdr_metadata!",
+        )?;
+        let swapchains = [swapchain];
+        let metadata = [metadata.to_vk()];
+        unsafe {
+            hdr_metadata_fn.set_hdr_metadata_ext(
+                self.device.handle.handle(),
+                swapchains.len() as _,
+                swapchains.as_ptr(),
+                metadata.as_ptr(),
+            );
+        }
+        Ok(())
+    }
+
     pub fn physical_device_format_properties(&self, format: vk::Format) -> vk::FormatProperties {
         unsafe {
             self.instance
@@ -197,6 +320,24 @@ impl Context {
         Ok(())
     }
 
+    /// Checks that images of the format can be created with optimal tiling and sampled,
+    /// which block compressed formats only are when their compression feature is supported
+    pub fn ensure_sampling_supported(&self, format: vk::Format) -> Result<()> {
+        let properties = self.physical_device_format_properties(format);
+
+        let format_supported = properties
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE);
+
+        ensure!(
+            format_supported,
+            "Sampling is not supported for format: {:?}",
+            format
+        );
+
+        Ok(())
+    }
+
     pub fn graphics_queue(&self) -> vk::Queue {
         let index = self.physical_device.graphics_queue_family_index;
         unsafe { self.device.handle.get_device_queue(index, 0) }
@@ -207,6 +348,71 @@ impl Context {
         unsafe { self.device.handle.get_device_queue(index, 0) }
     }
 
+    /// The dedicated transfer queue, if the device has one
+    pub fn transfer_queue(&self) -> Option<vk::Queue> {
+        self.physical_device
+            .transfer_queue_family_index
+            .map(|index| unsafe { self.device.handle.get_device_queue(index, 0) })
+    }
+
+    /// The compute mipmap generator, which is created the first time it is needed
+    pub fn mipmap_generator(&self) -> Result<Arc<MipmapGenerator>> {
+        let mut mipmap_generator = self
+            .mipmap_generator
+            .lock()
+            .ok()
+            .context("Failed to lock the mipmap generator!")?;
+        if let Some(generator) = mipmap_generator.as_ref() {
+            return Ok(generator.clone());
+        }
+        let generator = Arc::new(MipmapGenerator::new(self.device.clone())?);
+        *mipmap_generator = Some(generator.clone());
+        Ok(generator)
+    }
+
+    /// Allocator statistics per heap and memory type, heap budgets, and usage by category
+    pub fn memory_report(&self) -> Result<MemoryReport> {
+        let stats = self.allocator.calculate_stats()?;
+        let (properties, budget) = self
+            .physical_device
+            .memory_properties(&self.instance.handle, self.enabled_features.memory_budget);
+
+        let heaps = (0..properties.memory_heap_count as usize)
+            .map(|index| HeapReport {
+                index,
+                size: properties.memory_heaps[index].size,
+                flags: properties.memory_heaps[index].flags,
+                stats: allocation_stats(&stats.memoryHeap[index]),
+                budget: budget.map(|budget| HeapBudget {
+                    usage: budget.heap_usage[index],
+                    budget: budget.heap_budget[index],
+                }),
+            })
+            .collect();
+
+        let memory_types = (0..properties.memory_type_count as usize)
+            .map(|index| MemoryTypeReport {
+                index,
+                heap_index: properties.memory_types[index].heap_index,
+                property_flags: properties.memory_types[index].property_flags,
+                stats: allocation_stats(&stats.memoryType[index]),
+            })
+            .collect();
+
+        let memory_usage = self.device.memory_usage();
+        let categories = MemoryCategory::ALL
+            .iter()
+            .map(|category| (*category, memory_usage.bytes(*category)))
+            .collect();
+
+        Ok(MemoryReport {
+            total: allocation_stats(&stats.total),
+            heaps,
+            memory_types,
+            categories,
+        })
+    }
+
     pub fn physical_device_properties(&self) -> vk::PhysicalDeviceProperties {
         unsafe {
             self.instance
@@ -250,6 +456,43 @@ impl Context {
     }
 }
 
+#[derive(Default, Debug, Clone, Copy)]
+pub struct EnabledFeatures {
+    /// VK_EXT_swapchain_colorspace, which exposes the HDR10 and scRGB surface color spaces
+    pub swapchain_colorspace: bool,
+    /// VK_EXT_hdr_metadata
+    pub hdr_metadata: bool,
+    /// Vulkan 1.2 timeline semaphores
+    pub timeline_semaphore: bool,
+    /// Vulkan 1.2 buffer device addresses, which let shaders access buffers through pointers
+    pub buffer_device_address: bool,
+    /// VK_EXT_memory_budget, which reports heap usage and budgets including other processes
+    pub memory_budget: bool,
+    /// BC1-BC7 block compressed formats
+    pub texture_compression_bc: bool,
+    /// ETC2 and EAC block compressed formats
+    pub texture_compression_etc2: bool,
+    /// ASTC LDR block compressed formats
+    pub texture_compression_astc_ldr: bool,
+    /// Storage image writes without a format qualifier, which compute mipmap generation relies on
+    pub storage_image_write_without_format: bool,
+}
+
+fn allocation_stats(info: &vk_mem::ffi::VmaStatInfo) -> AllocationStats {
+    AllocationStats {
+        block_count: info.blockCount,
+        allocation_count: info.allocationCount,
+        used_bytes: info.usedBytes,
+        unused_bytes: info.unusedBytes,
+    }
+}
+
+fn extension_available(extensions: &[vk::ExtensionProperties], name: &CStr) -> bool {
+    extensions
+        .iter()
+        .any(|extension| unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) } == name)
+}
+
 pub struct Surface {
     pub handle_ash: AshSurface,
     pub handle_khr: SurfaceKHR,
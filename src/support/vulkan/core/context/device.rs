@@ -1,11 +1,36 @@
+use crate::vulkan::core::{
+    BlitImage, BufferToBufferCopy, BufferToImageCopy, ImageToBufferCopy, ImageToImageCopy,
+    MemoryUsage, PipelineBarrier, SamplerCache, SamplerDesc,
+};
 use anyhow::Result;
 use ash::{
     version::{DeviceV1_0, InstanceV1_0},
     vk,
 };
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+};
+
+type Destructor = Box<dyn FnOnce(&ash::Device) + Send>;
+
+struct DeferredDestruction {
+    frame_value: u64,
+    destroy: Destructor,
+}
 
 pub struct Device {
     pub handle: ash::Device,
+    deletion_queue: Mutex<Vec<DeferredDestruction>>,
+    queue_locks: Mutex<HashMap<vk::Queue, Arc<Mutex<()>>>>,
+    frame_value: AtomicU64,
+    completed_frame: AtomicU64,
+    memory_usage: MemoryUsage,
+    samplers: SamplerCache,
+    max_sampler_anisotropy: f32,
 }
 
 impl Device {
@@ -15,7 +40,142 @@ impl Device {
         create_info: vk::DeviceCreateInfoBuilder,
     ) -> Result<Self> {
         let handle = unsafe { instance.create_device(physical_device, &create_info, None) }?;
-        Ok(Self { handle })
+        let limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
+        Ok(Self {
+            handle,
+            deletion_queue: Mutex::new(Vec::new()),
+            queue_locks: Mutex::new(HashMap::new()),
+            frame_value: AtomicU64::new(0),
+            completed_frame: AtomicU64::new(0),
+            memory_usage: MemoryUsage::default(),
+            samplers: SamplerCache::default(),
+            max_sampler_anisotropy: limits.max_sampler_anisotropy,
+        })
+    }
+
+    /// The shared sampler for the description, created the first time it is asked for.
+    /// It lives as long as the device, so it must not be destroyed by the caller.
+    pub fn sampler(&self, desc: &SamplerDesc) -> Result<vk::Sampler> {
+        self.samplers
+            .get_or_create(&self.handle, desc, self.max_sampler_anisotropy)
+    }
+
+    pub fn max_sampler_anisotropy(&self) -> f32 {
+        self.max_sampler_anisotropy
+    }
+
+    /// Bytes allocated by the resources created from this device, by category
+    pub fn memory_usage(&self) -> &MemoryUsage {
+        &self.memory_usage
+    }
+
+    /// Sets the value of the latest frame that may reference objects dropped from now on,
+    /// which is the frame being recorded or the last one submitted.
+    /// Destruction deferred after this point waits for that frame to finish executing.
+    /// Objects dropped before the first frame is recorded only wait for the next collection.
+    pub fn set_frame_value(&self, frame_value: u64) {
+        self.frame_value.store(frame_value, Ordering::Release);
+    }
+
+    /// Queues a destructor to run once every frame that may reference the object has retired
+    pub fn defer_destruction(&self, destroy: impl FnOnce(&ash::Device) + Send + 'static) {
+        let frame_value = self.frame_value.load(Ordering::Acquire);
+        let mut deletion_queue = self
+            .deletion_queue
+            .lock()
+            .expect("Failed to lock the deletion queue!");
+        deletion_queue.push(DeferredDestruction {
+            frame_value,
+            destroy: Box::new(destroy),
+        });
+    }
+
+    /// Destroys everything queued by frames up to and including the completed frame
+    pub fn collect_garbage(&self, completed_frame: u64) {
+        let completed_frame = self
+            .completed_frame
+            .fetch_max(completed_frame, Ordering::AcqRel)
+            .max(completed_frame);
+        let ready = {
+            let mut deletion_queue = self
+                .deletion_queue
+                .lock()
+                .expect("Failed to lock the deletion queue!");
+            let (ready, pending) = deletion_queue
+                .drain(..)
+                .partition::<Vec<_>, _>(|entry| entry.frame_value <= completed_frame);
+            *deletion_queue = pending;
+            ready
+        };
+        // Destructors run outside the lock because they may queue more destruction
+        for entry in ready {
+            (entry.destroy)(&self.handle);
+        }
+    }
+
+    /// Destroys everything queued by frames that are already known to have completed.
+    /// This frees temporaries created outside of the render loop, such as while loading.
+    pub fn collect_completed_garbage(&self) {
+        self.collect_garbage(self.completed_frame.load(Ordering::Acquire));
+    }
+
+    fn flush_deletion_queue(&self) {
+        loop {
+            // Entries are only ever moved in and out of the queue whole,
+            // so a poisoned lock still holds everything that needs destroying
+            let entries = match self.deletion_queue.lock() {
+                Ok(mut deletion_queue) => std::mem::take(&mut *deletion_queue),
+                Err(poisoned) => std::mem::take(&mut *poisoned.into_inner()),
+            };
+            if entries.is_empty() {
+                return;
+            }
+            for entry in entries {
+                (entry.destroy)(&self.handle);
+            }
+        }
+    }
+
+    /// Runs the action with exclusive access to the queue.
+    /// Vulkan requires queue operations to be externally synchronized,
+    /// and uploads may be submitted from other threads than the one rendering.
+    pub fn with_queue<T>(&self, queue: vk::Queue, action: impl FnOnce() -> T) -> T {
+        let lock = self
+            .queue_locks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(queue)
+            .or_default()
+            .clone();
+        // The locks guard no data, so a thread that panicked while holding one left nothing inconsistent
+        let _guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        action()
+    }
+
+    pub fn queue_submit(
+        &self,
+        queue: vk::Queue,
+        submits: &[vk::SubmitInfo],
+        fence: vk::Fence,
+    ) -> Result<()> {
+        self.with_queue(queue, || unsafe {
+            self.handle.queue_submit(queue, submits, fence)
+        })?;
+        Ok(())
+    }
+
+    /// Waits for all queues to finish, holding every queue's lock as `vkDeviceWaitIdle` requires
+    pub fn wait_idle(&self) -> Result<()> {
+        let queue_locks = self
+            .queue_locks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let _guards = queue_locks
+            .values()
+            .map(|lock| lock.lock().unwrap_or_else(PoisonError::into_inner))
+            .collect::<Vec<_>>();
+        unsafe { self.handle.device_wait_idle() }?;
+        Ok(())
     }
 
     pub fn record_command_buffer(
@@ -31,6 +191,113 @@ impl Device {
         Ok(())
     }
 
+    /// Records a secondary command buffer that continues the render pass described by the inheritance info
+    pub fn record_secondary_command_buffer(
+        &self,
+        buffer: vk::CommandBuffer,
+        inheritance_info: &vk::CommandBufferInheritanceInfo,
+        mut action: impl FnMut(vk::CommandBuffer) -> Result<()>,
+    ) -> Result<()> {
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(
+                vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE
+                    | vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            )
+            .inheritance_info(inheritance_info);
+        unsafe { self.handle.begin_command_buffer(buffer, &begin_info) }?;
+        action(buffer)?;
+        unsafe { self.handle.end_command_buffer(buffer) }?;
+        Ok(())
+    }
+
+    pub fn copy_buffer_to_buffer(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        info: &BufferToBufferCopy,
+    ) {
+        unsafe {
+            self.handle.cmd_copy_buffer(
+                command_buffer,
+                info.source,
+                info.destination,
+                &info.regions,
+            )
+        };
+    }
+
+    pub fn copy_buffer_to_image(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        info: &BufferToImageCopy,
+    ) {
+        unsafe {
+            self.handle.cmd_copy_buffer_to_image(
+                command_buffer,
+                info.source,
+                info.destination,
+                info.dst_image_layout,
+                &info.regions,
+            )
+        };
+    }
+
+    pub fn copy_image_to_buffer(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        info: &ImageToBufferCopy,
+    ) {
+        unsafe {
+            self.handle.cmd_copy_image_to_buffer(
+                command_buffer,
+                info.source,
+                info.src_image_layout,
+                info.destination,
+                &info.regions,
+            )
+        };
+    }
+
+    pub fn copy_image_to_image(&self, command_buffer: vk::CommandBuffer, info: &ImageToImageCopy) {
+        unsafe {
+            self.handle.cmd_copy_image(
+                command_buffer,
+                info.source,
+                info.source_layout,
+                info.destination,
+                info.destination_layout,
+                &info.regions,
+            )
+        };
+    }
+
+    pub fn pipeline_barrier(&self, command_buffer: vk::CommandBuffer, info: &PipelineBarrier) {
+        unsafe {
+            self.handle.cmd_pipeline_barrier(
+                command_buffer,
+                info.src_stage_mask,
+                info.dst_stage_mask,
+                info.dependency_flags,
+                &info.memory_barriers,
+                &info.buffer_memory_barriers,
+                &info.image_memory_barriers,
+            )
+        };
+    }
+
+    pub fn blit_image(&self, command_buffer: vk::CommandBuffer, info: &BlitImage) {
+        unsafe {
+            self.handle.cmd_blit_image(
+                command_buffer,
+                info.src_image,
+                info.src_image_layout,
+                info.dst_image,
+                info.dst_image_layout,
+                &info.regions,
+                info.filter,
+            )
+        };
+    }
+
     pub fn update_viewport(
         &self,
         command_buffer: vk::CommandBuffer,
@@ -65,6 +332,10 @@ impl Device {
 impl Drop for Device {
     fn drop(&mut self) {
         unsafe {
+            // Nothing can still be in flight once the device is being destroyed
+            let _ = self.handle.device_wait_idle();
+            self.flush_deletion_queue();
+            self.samplers.destroy(&self.handle);
             self.handle.destroy_device(None);
         }
     }
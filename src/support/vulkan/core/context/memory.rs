@@ -0,0 +1,157 @@
+use ash::vk;
+use log::info;
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Who an allocation was made for, so memory usage can be broken down by purpose
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryCategory {
+    RenderGraph,
+    Textures,
+    Geometry,
+    Staging,
+    Other,
+}
+
+impl MemoryCategory {
+    pub const ALL: [MemoryCategory; 5] = [
+        MemoryCategory::RenderGraph,
+        MemoryCategory::Textures,
+        MemoryCategory::Geometry,
+        MemoryCategory::Staging,
+        MemoryCategory::Other,
+    ];
+
+    fn index(self) -> usize {
+        match self {
+            MemoryCategory::RenderGraph => 0,
+            MemoryCategory::Textures => 1,
+            MemoryCategory::Geometry => 2,
+            MemoryCategory::Staging => 3,
+            MemoryCategory::Other => 4,
+        }
+    }
+}
+
+/// Running totals of the bytes allocated in each category
+#[derive(Default)]
+pub struct MemoryUsage {
+    bytes: [AtomicU64; 5],
+}
+
+impl MemoryUsage {
+    pub fn allocate(&self, category: MemoryCategory, size: vk::DeviceSize) {
+        self.bytes[category.index()].fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub fn free(&self, category: MemoryCategory, size: vk::DeviceSize) {
+        self.bytes[category.index()].fetch_sub(size, Ordering::Relaxed);
+    }
+
+    pub fn bytes(&self, category: MemoryCategory) -> vk::DeviceSize {
+        self.bytes[category.index()].load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllocationStats {
+    pub block_count: u32,
+    pub allocation_count: u32,
+    pub used_bytes: vk::DeviceSize,
+    pub unused_bytes: vk::DeviceSize,
+}
+
+/// Usage and budget reported by VK_EXT_memory_budget, which include other processes
+#[derive(Debug, Clone, Copy)]
+pub struct HeapBudget {
+    pub usage: vk::DeviceSize,
+    pub budget: vk::DeviceSize,
+}
+
+#[derive(Debug, Clone)]
+pub struct HeapReport {
+    pub index: usize,
+    pub size: vk::DeviceSize,
+    pub flags: vk::MemoryHeapFlags,
+    pub stats: AllocationStats,
+    pub budget: Option<HeapBudget>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryTypeReport {
+    pub index: usize,
+    pub heap_index: u32,
+    pub property_flags: vk::MemoryPropertyFlags,
+    pub stats: AllocationStats,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryReport {
+    pub total: AllocationStats,
+    pub heaps: Vec<HeapReport>,
+    pub memory_types: Vec<MemoryTypeReport>,
+    pub categories: Vec<(MemoryCategory, vk::DeviceSize)>,
+}
+
+impl MemoryReport {
+    pub fn log(&self) {
+        self.to_string().lines().for_each(|line| info!("{}", line));
+    }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "GPU memory: {} used, {} unused in {} blocks, {} allocations",
+            megabytes(self.total.used_bytes),
+            megabytes(self.total.unused_bytes),
+            self.total.block_count,
+            self.total.allocation_count
+        )?;
+        for heap in self.heaps.iter() {
+            write!(
+                f,
+                "  Heap {} ({}, {:?}): {} used, {} unused",
+                heap.index,
+                megabytes(heap.size),
+                heap.flags,
+                megabytes(heap.stats.used_bytes),
+                megabytes(heap.stats.unused_bytes)
+            )?;
+            match heap.budget {
+                Some(budget) => writeln!(
+                    f,
+                    ", budget {} of {}",
+                    megabytes(budget.usage),
+                    megabytes(budget.budget)
+                )?,
+                None => writeln!(f)?,
+            }
+        }
+        for memory_type in self.memory_types.iter() {
+            if memory_type.stats.block_count == 0 {
+                continue;
+            }
+            writeln!(
+                f,
+                "  Type {} (heap {}, {:?}): {} used in {} allocations",
+                memory_type.index,
+                memory_type.heap_index,
+                memory_type.property_flags,
+                megabytes(memory_type.stats.used_bytes),
+                memory_type.stats.allocation_count
+            )?;
+        }
+        for (category, bytes) in self.categories.iter() {
+            writeln!(f, "  {:?}: {}", category, megabytes(*bytes))?;
+        }
+        Ok(())
+    }
+}
+
+fn megabytes(bytes: vk::DeviceSize) -> String {
+    format!("{:.2} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
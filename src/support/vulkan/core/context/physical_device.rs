@@ -1,6 +1,10 @@
+use super::extension_available;
 use crate::vulkan::core::Surface;
 use anyhow::{anyhow, Result};
-use ash::{version::InstanceV1_0, vk};
+use ash::{
+    version::{InstanceV1_0, InstanceV1_1},
+    vk,
+};
 use log::info;
 use std::ffi::CStr;
 
@@ -8,6 +12,8 @@ pub struct PhysicalDevice {
     pub handle: vk::PhysicalDevice,
     pub graphics_queue_family_index: u32,
     pub presentation_queue_family_index: u32,
+    /// A queue family dedicated to transfers, if the device exposes one
+    pub transfer_queue_family_index: Option<u32>,
 }
 
 impl PhysicalDevice {
@@ -37,12 +43,14 @@ impl PhysicalDevice {
         }
 
         let (graphics_queue_family_index, presentation_queue_family_index) = queue_indices.unwrap();
+        let transfer_queue_family_index = Self::find_transfer_queue_family_index(instance, device);
 
         info!("Selected physical device: {:?}", device_name);
         let physical_device = Self {
             handle: device,
             graphics_queue_family_index,
             presentation_queue_family_index,
+            transfer_queue_family_index,
         };
 
         Ok(Some(physical_device))
@@ -97,6 +105,31 @@ impl PhysicalDevice {
         Ok(indices)
     }
 
+    fn find_transfer_queue_family_index(
+        instance: &ash::Instance,
+        device: vk::PhysicalDevice,
+    ) -> Option<u32> {
+        let queue_family_properties =
+            unsafe { instance.get_physical_device_queue_family_properties(device) };
+
+        let transfer_families = queue_family_properties
+            .iter()
+            .enumerate()
+            .filter(|(_, family)| {
+                family.queue_count > 0
+                    && family.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                    && !family.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+            })
+            .collect::<Vec<_>>();
+
+        // Transfer-only families usually map to dedicated copy engines
+        transfer_families
+            .iter()
+            .find(|(_, family)| !family.queue_flags.contains(vk::QueueFlags::COMPUTE))
+            .or_else(|| transfer_families.first())
+            .map(|(index, _)| *index as u32)
+    }
+
     fn check_queue_families(
         queue_family_properties: Vec<vk::QueueFamilyProperties>,
         device: vk::PhysicalDevice,
@@ -145,6 +178,59 @@ impl PhysicalDevice {
         required_features.iter().all(|feature| *feature == vk::TRUE)
     }
 
+    pub fn features(&self, instance: &ash::Instance) -> vk::PhysicalDeviceFeatures {
+        unsafe { instance.get_physical_device_features(self.handle) }
+    }
+
+    pub fn api_version(&self, instance: &ash::Instance) -> u32 {
+        unsafe { instance.get_physical_device_properties(self.handle) }.api_version
+    }
+
+    pub fn supports_vulkan12(&self, instance: &ash::Instance) -> bool {
+        self.api_version(instance) >= vk::make_version(1, 2, 0)
+    }
+
+    /// Devices below Vulkan 1.2 cannot be queried for these, so none are reported as supported
+    pub fn vulkan12_features(
+        &self,
+        instance: &ash::Instance,
+    ) -> vk::PhysicalDeviceVulkan12Features {
+        if !self.supports_vulkan12(instance) {
+            return vk::PhysicalDeviceVulkan12Features::default();
+        }
+        let mut vulkan12_features = vk::PhysicalDeviceVulkan12Features::default();
+        let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut vulkan12_features);
+        unsafe { instance.get_physical_device_features2(self.handle, &mut features) };
+        vulkan12_features.p_next = std::ptr::null_mut();
+        vulkan12_features
+    }
+
+    /// Heap budgets can only be queried when VK_EXT_memory_budget is enabled
+    pub fn memory_properties(
+        &self,
+        instance: &ash::Instance,
+        query_budget: bool,
+    ) -> (
+        vk::PhysicalDeviceMemoryProperties,
+        Option<vk::PhysicalDeviceMemoryBudgetPropertiesEXT>,
+    ) {
+        if !query_budget {
+            let properties = unsafe { instance.get_physical_device_memory_properties(self.handle) };
+            return (properties, None);
+        }
+        let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut properties = vk::PhysicalDeviceMemoryProperties2::builder().push_next(&mut budget);
+        unsafe { instance.get_physical_device_memory_properties2(self.handle, &mut properties) };
+        let memory_properties = properties.memory_properties;
+        budget.p_next = std::ptr::null_mut();
+        (memory_properties, Some(budget))
+    }
+
+    pub fn supports_extension(&self, instance: &ash::Instance, name: &CStr) -> Result<bool> {
+        let extensions = unsafe { instance.enumerate_device_extension_properties(self.handle) }?;
+        Ok(extension_available(&extensions, name))
+    }
+
     pub fn queue_indices(&self) -> Vec<u32> {
         vec![
             self.graphics_queue_family_index,
@@ -1,22 +1,55 @@
 use crate::vulkan::core::{
-    create_swapchain, CommandPool, Context, Device, Fence, Semaphore, Swapchain,
-    SwapchainProperties,
+    create_swapchain, format_texel_size, image_layout_barrier, ColorSpace, CommandPool, Context,
+    Device, Fence, HdrMetadata, ImageDescription, ImageLayoutTransitionBuilder,
+    ImageToBufferCopyBuilder, MipmapFilter, OutputColorSpace, ReadbackBuffer, Semaphore,
+    StagedRegion, StagingLease, StagingRelease, Swapchain, SwapchainConfig, SwapchainProperties,
+    TimelineSemaphore,
 };
-use anyhow::{bail, Context as AnyhowContext, Result};
+use anyhow::{anyhow, bail, ensure, Context as AnyhowContext, Result};
 use ash::{
     prelude::VkResult,
     version::DeviceV1_0,
     vk::{self, Handle},
 };
-use std::sync::Arc;
+use derive_builder::Builder;
+use log::{error, info, warn};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Builder, Clone, Debug)]
+#[builder(default)]
+pub struct FrameConfig {
+    /// Timeline semaphores require Vulkan 1.2.
+    /// Fences are used instead when the device does not support them.
+    pub sync_backend: FrameSyncBackend,
+}
+
+impl Default for FrameConfig {
+    fn default() -> Self {
+        Self {
+            sync_backend: FrameSyncBackend::Fences,
+        }
+    }
+}
 
 pub struct Frame {
     index: usize,
+    frame_number: u64,
+    completed_frame: u64,
+    slot_frames: Vec<u64>,
     locks: Vec<FrameLock>,
-    command_buffers: Vec<vk::CommandBuffer>,
-    _command_pool: CommandPool,
+    timeline: Option<TimelineSemaphore>,
+    commands: Vec<FrameCommands>,
     frames_in_flight: usize,
     swapchain: Option<Swapchain>,
+    retired_swapchains: Vec<RetiredSwapchain>,
+    swapchain_config: SwapchainConfig,
+    recreation_requested: bool,
+    hdr_metadata: Option<HdrMetadata>,
+    capture_request: Option<PathBuf>,
+    pending_captures: Vec<PendingCapture>,
     pub swapchain_properties: SwapchainProperties,
     pub recreated_swapchain: bool,
     context: Arc<Context>,
@@ -27,6 +60,8 @@ impl Frame {
         context: Arc<Context>,
         dimensions: &[u32; 2],
         frames_in_flight: usize,
+        swapchain_config: SwapchainConfig,
+        frame_config: &FrameConfig,
     ) -> Result<Self> {
         let frame_locks = (0..frames_in_flight)
             .map(|index| {
@@ -36,27 +71,40 @@ impl Frame {
             })
             .collect::<Result<Vec<_>>>()?;
 
-        let graphics_queue_index = context.physical_device.graphics_queue_family_index;
-        let command_pool = CommandPool::new(
-            context.device.clone(),
-            context.graphics_queue(),
-            vk::CommandPoolCreateInfo::builder()
-                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
-                .queue_family_index(graphics_queue_index),
-        )?;
+        let timeline = match frame_config.sync_backend {
+            FrameSyncBackend::TimelineSemaphore if context.enabled_features.timeline_semaphore => {
+                Some(TimelineSemaphore::new(context.device.clone(), 0)?)
+            }
+            FrameSyncBackend::TimelineSemaphore => {
+                warn!("Timeline semaphores are not supported, falling back to fences");
+                None
+            }
+            FrameSyncBackend::Fences => None,
+        };
+
+        let commands = (0..frames_in_flight)
+            .map(|_| FrameCommands::new(&context))
+            .collect::<Result<Vec<_>>>()?;
 
-        let (swapchain, properties) = create_swapchain(&context, dimensions)?;
-        let number_of_framebuffers = swapchain.images()?.len() as _;
-        let command_buffers = command_pool
-            .allocate_command_buffers(number_of_framebuffers, vk::CommandBufferLevel::PRIMARY)?;
+        let (swapchain, properties) =
+            create_swapchain(&context, dimensions, &swapchain_config, None)?;
 
         Ok(Self {
             index: 0,
+            frame_number: 0,
+            completed_frame: 0,
+            slot_frames: vec![0; frames_in_flight],
             locks: frame_locks,
-            command_buffers,
-            _command_pool: command_pool,
+            timeline,
+            commands,
             frames_in_flight,
             swapchain: Some(swapchain),
+            retired_swapchains: Vec::new(),
+            swapchain_config,
+            recreation_requested: false,
+            hdr_metadata: None,
+            capture_request: None,
+            pending_captures: Vec::new(),
             recreated_swapchain: false,
             swapchain_properties: properties,
             context,
@@ -67,21 +115,209 @@ impl Frame {
         self.swapchain.as_ref().context("Failed to get swapchain!")
     }
 
+    /// The slot used by the frame currently being recorded
+    pub fn frame_index(&self) -> usize {
+        self.index
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames_in_flight
+    }
+
+    pub fn sync_backend(&self) -> FrameSyncBackend {
+        if self.timeline.is_some() {
+            FrameSyncBackend::TimelineSemaphore
+        } else {
+            FrameSyncBackend::Fences
+        }
+    }
+
+    /// The value that will be signaled once the frame currently being recorded has finished executing.
+    /// Frame values start at 1 and increase monotonically.
+    pub fn frame_value(&self) -> u64 {
+        self.frame_number + 1
+    }
+
+    /// The value of the most recent frame known to have finished executing
+    pub fn completed_frame(&self) -> Result<u64> {
+        if let Some(timeline) = self.timeline.as_ref() {
+            return timeline.value();
+        }
+        let mut completed_frame = self.completed_frame;
+        for (lock, frame_value) in self.locks.iter().zip(self.slot_frames.iter()) {
+            if *frame_value > completed_frame
+                && unsafe {
+                    self.context
+                        .device
+                        .handle
+                        .get_fence_status(lock.in_flight.handle)
+                }?
+            {
+                completed_frame = *frame_value;
+            }
+        }
+        Ok(completed_frame)
+    }
+
+    pub fn is_frame_complete(&self, frame_value: u64) -> Result<bool> {
+        Ok(frame_value <= self.completed_frame()?)
+    }
+
+    pub fn wait_for_frame(&self, frame_value: u64) -> Result<()> {
+        if frame_value > self.frame_number {
+            bail!("Frame {} has not been submitted yet!", frame_value);
+        }
+        if let Some(timeline) = self.timeline.as_ref() {
+            return timeline.wait(frame_value, std::u64::MAX);
+        }
+        if frame_value <= self.completed_frame {
+            return Ok(());
+        }
+        // A later frame in the same slot means this one was already waited on
+        let slot = ((frame_value - 1) % self.frames_in_flight as u64) as usize;
+        if self.slot_frames[slot] == frame_value {
+            let fence = self.locks[slot].in_flight.handle;
+            unsafe {
+                self.context
+                    .device
+                    .handle
+                    .wait_for_fences(&[fence], true, std::u64::MAX)
+            }?;
+        }
+        Ok(())
+    }
+
+    pub fn swapchain_config(&self) -> &SwapchainConfig {
+        &self.swapchain_config
+    }
+
+    /// The swapchain is recreated with the new configuration on the next call to `render`
+    pub fn set_swapchain_config(&mut self, swapchain_config: SwapchainConfig) {
+        self.swapchain_config = swapchain_config;
+        self.recreation_requested = true;
+    }
+
+    /// Copies the next presented image to disk once its frame has finished rendering.
+    /// See `ImageDescription::save` for the file formats that are written.
+    pub fn capture_next(&mut self, path: impl Into<PathBuf>) -> Result<()> {
+        ensure!(
+            self.swapchain_properties
+                .image_usage
+                .contains(vk::ImageUsageFlags::TRANSFER_SRC),
+            "The surface does not allow copying from swapchain images!"
+        );
+        let format = self.swapchain_properties.surface_format.format;
+        ensure!(
+            ImageDescription::is_saveable(format),
+            "Capturing swapchain images with format {:?} is not supported!",
+            format
+        );
+        self.capture_request = Some(path.into());
+        Ok(())
+    }
+
+    /// The metadata is reapplied whenever the swapchain is recreated
+    pub fn set_hdr_metadata(&mut self, metadata: HdrMetadata) -> Result<()> {
+        self.context
+            .set_hdr_metadata(self.swapchain()?.handle_khr, &metadata)?;
+        self.hdr_metadata = Some(metadata);
+        Ok(())
+    }
+
+    /// Records a single command buffer for the acquired swapchain image
     pub fn render(
         &mut self,
         dimensions: &[u32; 2],
         mut action: impl FnMut(vk::CommandBuffer, usize) -> Result<()>,
+    ) -> Result<()> {
+        self.render_batches(dimensions, |recorder, image_index| {
+            let command_buffer =
+                recorder.record(|command_buffer| action(command_buffer, image_index))?;
+            let batch = SubmitBatchBuilder::default()
+                .command_buffers(vec![command_buffer])
+                .build()
+                .map_err(|error| anyhow!("{}", error))?;
+            recorder.submit(batch);
+            Ok(())
+        })
+    }
+
+    /// Records any number of command buffers and submits them in batches.
+    ///
+    /// Batches are submitted in order once the action returns.
+    /// The last batch additionally waits for the acquired swapchain image
+    /// and signals the semaphore that presentation waits on,
+    /// so it should contain the work that writes to the swapchain image.
+    pub fn render_batches(
+        &mut self,
+        dimensions: &[u32; 2],
+        mut action: impl FnMut(&mut FrameRecorder, usize) -> Result<()>,
     ) -> Result<()> {
         self.recreated_swapchain = false;
+        if dimensions[0] == 0 || dimensions[1] == 0 {
+            // The window is minimized, so the swapchain is recreated once it is restored
+            self.recreation_requested = true;
+            return Ok(());
+        }
         self.wait_for_in_flight_fence()?;
+        self.destroy_retired_swapchains()?;
+        let completed_frame = self.completed_frame()?;
+        self.context.device.collect_garbage(completed_frame);
+        self.context.staging.reclaim(completed_frame)?;
+        self.save_completed_captures(completed_frame);
+        if self.recreation_requested {
+            // Skipping the frame lets the app rebuild anything that refers to the old swapchain
+            // before recording commands against the new one
+            self.create_swapchain(dimensions)?;
+            return Ok(());
+        }
         if let Some(image_index) = self.acquire_next_frame(dimensions)? {
-            self.reset_in_flight_fence()?;
-            self.context.device.record_command_buffer(
-                self.command_buffer_at(image_index)?,
-                vk::CommandBufferUsageFlags::empty(),
-                |command_buffer| action(command_buffer, image_index),
-            )?;
-            self.submit_command_buffer(image_index)?;
+            let frame_value = self.frame_value();
+            let capture_image = match self.capture_request.as_ref() {
+                Some(_) => Some(self.swapchain()?.images()?[image_index]),
+                None => None,
+            };
+            let (batches, capture) = {
+                let commands = self
+                    .commands
+                    .get_mut(self.index)
+                    .context("Failed to get the command pool for the current frame!")?;
+                commands.reset()?;
+                self.context.device.set_frame_value(frame_value);
+                let mut recorder = FrameRecorder {
+                    device: &self.context.device,
+                    commands,
+                    staging: self.context.staging.lease(),
+                    batches: Vec::new(),
+                };
+                action(&mut recorder, image_index)?;
+                let capture = match (capture_image, self.capture_request.take()) {
+                    (Some(image), Some(path)) => Some(record_capture(
+                        &mut recorder,
+                        &self.context,
+                        image,
+                        &self.swapchain_properties,
+                        path,
+                        frame_value,
+                    )?),
+                    _ => None,
+                };
+                // A failed submission is retried with the same frame value,
+                // so the staged data is never recycled before it has been read
+                recorder
+                    .staging
+                    .retire(StagingRelease::Frame(frame_value))?;
+                (recorder.batches, capture)
+            };
+            let batches = match capture {
+                Some((command_buffer, capture)) => {
+                    self.pending_captures.push(capture);
+                    append_to_final_batch(batches, command_buffer)
+                }
+                None => batches,
+            };
+            self.submit_batches(batches)?;
+            self.slot_frames[self.index] = self.frame_value();
             let result = self.present_next_frame(image_index)?;
             self.check_presentation_result(result, dimensions)?;
             self.increment_frame_counter();
@@ -91,22 +327,57 @@ impl Frame {
 
     fn increment_frame_counter(&mut self) {
         self.index = (self.index + 1) % self.frames_in_flight;
+        self.frame_number += 1;
+    }
+
+    fn save_completed_captures(&mut self, completed_frame: u64) {
+        let (completed, pending) = self
+            .pending_captures
+            .drain(..)
+            .partition(|capture| capture.frame_value <= completed_frame);
+        self.pending_captures = pending;
+        for capture in completed.into_iter() {
+            match capture.save() {
+                Ok(path) => info!("Saved capture to {}", path.display()),
+                Err(error) => error!("Failed to save capture: {}", error),
+            }
+        }
+    }
+
+    /// Swapchains are retired rather than waited on, because the frames still in flight may use them.
+    /// Everything else built on a retired swapchain, such as its image views and framebuffers,
+    /// is released through the device's deletion queue once those frames have completed.
+    fn destroy_retired_swapchains(&mut self) -> Result<()> {
+        let completed_frame = self.completed_frame()?;
+        self.retired_swapchains
+            .retain(|retired_swapchain| retired_swapchain.frame_value > completed_frame);
+        Ok(())
     }
 
     fn reset_in_flight_fence(&self) -> Result<()> {
+        if self.timeline.is_some() {
+            return Ok(());
+        }
         let in_flight_fence = self.frame_lock()?.in_flight.handle;
         unsafe { self.context.device.handle.reset_fences(&[in_flight_fence]) }?;
         Ok(())
     }
 
-    fn wait_for_in_flight_fence(&self) -> Result<()> {
-        let fence = self.frame_lock()?.in_flight.handle;
-        unsafe {
-            self.context
-                .device
-                .handle
-                .wait_for_fences(&[fence], true, std::u64::MAX)
-        }?;
+    fn wait_for_in_flight_fence(&mut self) -> Result<()> {
+        let frame_value = self.slot_frames[self.index];
+        match self.timeline.as_ref() {
+            Some(timeline) => timeline.wait(frame_value, std::u64::MAX)?,
+            None => {
+                let fence = self.frame_lock()?.in_flight.handle;
+                unsafe {
+                    self.context
+                        .device
+                        .handle
+                        .wait_for_fences(&[fence], true, std::u64::MAX)
+                }?;
+            }
+        }
+        self.completed_frame = self.completed_frame.max(frame_value);
         Ok(())
     }
 
@@ -135,11 +406,11 @@ impl Frame {
             .swapchains(&swapchains)
             .image_indices(&image_indices);
 
-        let presentation_result = unsafe {
-            self.swapchain()?
-                .handle_ash
-                .queue_present(self.context.presentation_queue(), &present_info)
-        };
+        let swapchain = self.swapchain()?;
+        let queue = self.context.presentation_queue();
+        let presentation_result = self.context.device.with_queue(queue, || unsafe {
+            swapchain.handle_ash.queue_present(queue, &present_info)
+        });
 
         Ok(presentation_result)
     }
@@ -167,26 +438,32 @@ impl Frame {
             return Ok(());
         }
 
-        unsafe { self.context.device.handle.device_wait_idle() }?;
-
-        self.swapchain = None;
-        let (swapchain, properties) = create_swapchain(&self.context, dimensions)?;
-        self.swapchain = Some(swapchain);
+        let (swapchain, properties) = create_swapchain(
+            &self.context,
+            dimensions,
+            &self.swapchain_config,
+            self.swapchain.as_ref(),
+        )?;
+        if let Some(old_swapchain) = self.swapchain.replace(swapchain) {
+            // The frame currently being rendered may still be using the old swapchain
+            self.retired_swapchains.push(RetiredSwapchain {
+                _swapchain: old_swapchain,
+                frame_value: self.frame_value(),
+            });
+        }
         self.swapchain_properties = properties;
 
+        if let Some(metadata) = self.hdr_metadata.as_ref() {
+            self.context
+                .set_hdr_metadata(self.swapchain()?.handle_khr, metadata)?;
+        }
+
+        self.recreation_requested = false;
         self.recreated_swapchain = true;
 
         Ok(())
     }
 
-    fn command_buffer_at(&self, image_index: usize) -> Result<vk::CommandBuffer> {
-        let command_buffer = *self.command_buffers.get(image_index).context(format!(
-            "No command buffer was found at image index: {}",
-            image_index
-        ))?;
-        Ok(command_buffer)
-    }
-
     fn frame_lock(&self) -> Result<&FrameLock> {
         let lock = &self.locks.get(self.index).context(format!(
             "No frame lock was found at frame index: {}",
@@ -195,28 +472,539 @@ impl Frame {
         Ok(lock)
     }
 
-    fn submit_command_buffer(&self, image_index: usize) -> Result<()> {
+    fn submit_batches(&self, mut batches: Vec<SubmitBatch>) -> Result<()> {
         let lock = self.frame_lock()?;
-        let image_available_semaphores = [lock.image_available.handle];
-        let wait_semaphores = [lock.render_finished.handle];
-        let command_buffers = [self.command_buffer_at(image_index)?];
-
-        let submit_info = vk::SubmitInfo::builder()
-            .wait_semaphores(&image_available_semaphores)
-            .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
-            .command_buffers(&command_buffers)
-            .signal_semaphores(&wait_semaphores);
-
-        unsafe {
-            self.context.device.handle.queue_submit(
-                self.context.graphics_queue(),
-                &[submit_info.build()],
-                lock.in_flight.handle,
-            )
-        }?;
+        let mut final_batch = batches.pop().unwrap_or_default();
+        final_batch.wait_semaphores.push(SemaphoreWait::binary(
+            lock.image_available.handle,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        ));
+        final_batch
+            .signal_semaphores
+            .push(SemaphoreSignal::binary(lock.render_finished.handle));
+
+        let fence = match self.timeline.as_ref() {
+            Some(timeline) => {
+                final_batch
+                    .signal_semaphores
+                    .push(SemaphoreSignal::timeline(
+                        timeline.handle,
+                        self.frame_value(),
+                    ));
+                vk::Fence::null()
+            }
+            None => lock.in_flight.handle,
+        };
+
+        for batch in batches.iter() {
+            self.submit_batch(batch, vk::Fence::null())?;
+        }
+
+        // The fence is only reset once nothing can fail before it is submitted
+        self.reset_in_flight_fence()?;
+        self.submit_batch(&final_batch, fence)
+    }
+
+    fn submit_batch(&self, batch: &SubmitBatch, fence: vk::Fence) -> Result<()> {
+        let wait_semaphores = batch
+            .wait_semaphores
+            .iter()
+            .map(|wait| wait.semaphore)
+            .collect::<Vec<_>>();
+        let wait_stages = batch
+            .wait_semaphores
+            .iter()
+            .map(|wait| wait.stage_mask)
+            .collect::<Vec<_>>();
+        let wait_values = batch
+            .wait_semaphores
+            .iter()
+            .map(|wait| wait.value.unwrap_or_default())
+            .collect::<Vec<_>>();
+        let signal_semaphores = batch
+            .signal_semaphores
+            .iter()
+            .map(|signal| signal.semaphore)
+            .collect::<Vec<_>>();
+        let signal_values = batch
+            .signal_semaphores
+            .iter()
+            .map(|signal| signal.value.unwrap_or_default())
+            .collect::<Vec<_>>();
+
+        // Binary semaphores ignore their entries in the value arrays
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .wait_semaphore_values(&wait_values)
+            .signal_semaphore_values(&signal_values);
+
+        let mut submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&batch.command_buffers)
+            .signal_semaphores(&signal_semaphores);
+        if batch.uses_timeline_semaphores() {
+            submit_info = submit_info.push_next(&mut timeline_info);
+        }
+
+        self.context.device.queue_submit(
+            self.context.graphics_queue(),
+            &[submit_info.build()],
+            fence,
+        )
+    }
+}
+
+/// Command buffers recorded for one frame in flight.
+/// The pools are reset once the GPU has finished the frame that last used them.
+struct FrameCommands {
+    primary: CommandBufferCache,
+    secondary: SecondaryCommandPools,
+}
+
+impl FrameCommands {
+    fn new(context: &Context) -> Result<Self> {
+        Ok(Self {
+            primary: CommandBufferCache::new(context, vk::CommandBufferLevel::PRIMARY)?,
+            secondary: SecondaryCommandPools::new(context)?,
+        })
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.primary.reset()?;
+        self.secondary.reset()
+    }
+}
+
+struct CommandBufferCache {
+    pool: CommandPool,
+    level: vk::CommandBufferLevel,
+    command_buffers: Vec<vk::CommandBuffer>,
+    next: usize,
+}
+
+impl CommandBufferCache {
+    fn new(context: &Context, level: vk::CommandBufferLevel) -> Result<Self> {
+        let pool = CommandPool::new(
+            context.device.clone(),
+            context.graphics_queue(),
+            vk::CommandPoolCreateInfo::builder()
+                .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+                .queue_family_index(context.physical_device.graphics_queue_family_index),
+        )?;
+        Ok(Self {
+            pool,
+            level,
+            command_buffers: Vec::new(),
+            next: 0,
+        })
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.pool.reset()?;
+        self.next = 0;
+        Ok(())
+    }
+
+    fn next_command_buffer(&mut self) -> Result<vk::CommandBuffer> {
+        if self.next == self.command_buffers.len() {
+            let command_buffers = self.pool.allocate_command_buffers(1, self.level)?;
+            self.command_buffers.extend(command_buffers);
+        }
+        let command_buffer = self.command_buffers[self.next];
+        self.next += 1;
+        Ok(command_buffer)
+    }
+}
+
+/// One secondary command pool per rayon worker thread, plus one for the calling thread.
+/// Command pools are externally synchronized, so each thread records into its own.
+pub struct SecondaryCommandPools {
+    pools: Vec<Mutex<CommandBufferCache>>,
+    device: Arc<Device>,
+}
+
+impl SecondaryCommandPools {
+    fn new(context: &Context) -> Result<Self> {
+        let pools = (0..rayon::current_num_threads() + 1)
+            .map(|_| {
+                let cache = CommandBufferCache::new(context, vk::CommandBufferLevel::SECONDARY)?;
+                Ok(Mutex::new(cache))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            pools,
+            device: context.device.clone(),
+        })
+    }
 
+    fn reset(&mut self) -> Result<()> {
+        for pool in self.pools.iter_mut() {
+            pool.get_mut()
+                .map_err(|_| anyhow!("Failed to access a secondary command pool!"))?
+                .reset()?;
+        }
         Ok(())
     }
+
+    /// Records a secondary command buffer for the first subpass of the render pass
+    /// using the pool that belongs to the current thread
+    pub fn record(
+        &self,
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+        action: impl FnMut(vk::CommandBuffer) -> Result<()>,
+    ) -> Result<vk::CommandBuffer> {
+        let index = rayon::current_thread_index()
+            .map(|index| index % (self.pools.len() - 1))
+            .unwrap_or(self.pools.len() - 1);
+        let mut pool = self.pools[index]
+            .lock()
+            .map_err(|_| anyhow!("Failed to lock a secondary command pool!"))?;
+        let command_buffer = pool.next_command_buffer()?;
+        let inheritance_info = vk::CommandBufferInheritanceInfo::builder()
+            .render_pass(render_pass)
+            .subpass(0)
+            .framebuffer(framebuffer);
+        self.device
+            .record_secondary_command_buffer(command_buffer, &inheritance_info, action)?;
+        Ok(command_buffer)
+    }
+}
+
+/// Records command buffers for the current frame and queues them for submission
+pub struct FrameRecorder<'a> {
+    device: &'a Device,
+    commands: &'a mut FrameCommands,
+    staging: StagingLease,
+    batches: Vec<SubmitBatch>,
+}
+
+impl<'a> FrameRecorder<'a> {
+    /// Records a primary command buffer that stays valid until this frame in flight is reused
+    pub fn record(
+        &mut self,
+        action: impl FnMut(vk::CommandBuffer) -> Result<()>,
+    ) -> Result<vk::CommandBuffer> {
+        let command_buffer = self.commands.primary.next_command_buffer()?;
+        self.device.record_command_buffer(
+            command_buffer,
+            vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            action,
+        )?;
+        Ok(command_buffer)
+    }
+
+    /// Pools for recording secondary command buffers from multiple threads
+    pub fn secondary_pools(&self) -> &SecondaryCommandPools {
+        &self.commands.secondary
+    }
+
+    /// Copies the data into the staging ring for commands recorded this frame to read.
+    /// The region is recycled once this frame has finished executing.
+    pub fn stage<T: Copy>(&mut self, data: &[T]) -> Result<StagedRegion> {
+        self.staging.push(data)
+    }
+
+    /// Like `stage`, but places the data at an offset that is a multiple of the alignment,
+    /// such as `format_copy_alignment` for copies into images
+    pub fn stage_aligned<T: Copy>(
+        &mut self,
+        data: &[T],
+        alignment: vk::DeviceSize,
+    ) -> Result<StagedRegion> {
+        self.staging.push_aligned(data, alignment)
+    }
+
+    pub fn submit(&mut self, batch: SubmitBatch) {
+        self.batches.push(batch);
+    }
+}
+
+/// Command buffers submitted together to the graphics queue,
+/// which is the queue family the frame's command pools are created for
+#[derive(Builder, Default)]
+#[builder(default)]
+pub struct SubmitBatch {
+    pub command_buffers: Vec<vk::CommandBuffer>,
+    pub wait_semaphores: Vec<SemaphoreWait>,
+    pub signal_semaphores: Vec<SemaphoreSignal>,
+}
+
+impl SubmitBatch {
+    fn uses_timeline_semaphores(&self) -> bool {
+        self.wait_semaphores.iter().any(|wait| wait.value.is_some())
+            || self
+                .signal_semaphores
+                .iter()
+                .any(|signal| signal.value.is_some())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SemaphoreWait {
+    pub semaphore: vk::Semaphore,
+    pub stage_mask: vk::PipelineStageFlags,
+    /// The value to wait for, which only timeline semaphores have
+    pub value: Option<u64>,
+}
+
+impl SemaphoreWait {
+    pub fn binary(semaphore: vk::Semaphore, stage_mask: vk::PipelineStageFlags) -> Self {
+        Self {
+            semaphore,
+            stage_mask,
+            value: None,
+        }
+    }
+
+    pub fn timeline(
+        semaphore: vk::Semaphore,
+        stage_mask: vk::PipelineStageFlags,
+        value: u64,
+    ) -> Self {
+        Self {
+            semaphore,
+            stage_mask,
+            value: Some(value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SemaphoreSignal {
+    pub semaphore: vk::Semaphore,
+    /// The value to signal, which only timeline semaphores have
+    pub value: Option<u64>,
+}
+
+impl SemaphoreSignal {
+    pub fn binary(semaphore: vk::Semaphore) -> Self {
+        Self {
+            semaphore,
+            value: None,
+        }
+    }
+
+    pub fn timeline(semaphore: vk::Semaphore, value: u64) -> Self {
+        Self {
+            semaphore,
+            value: Some(value),
+        }
+    }
+}
+
+/// Holds one resource per frame in flight.
+///
+/// The current slot should only be written from inside the `Frame::render` callback,
+/// after the GPU has finished the last frame that used it.
+pub struct PerFrame<T> {
+    slots: Vec<T>,
+}
+
+impl<T> PerFrame<T> {
+    pub fn new(
+        frames_in_flight: usize,
+        mut create: impl FnMut(usize) -> Result<T>,
+    ) -> Result<Self> {
+        let slots = (0..frames_in_flight)
+            .map(|index| create(index))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { slots })
+    }
+
+    pub fn current(&self, frame: &Frame) -> Result<&T> {
+        self.get(frame.frame_index())
+    }
+
+    pub fn current_mut(&mut self, frame: &Frame) -> Result<&mut T> {
+        let index = frame.frame_index();
+        self.slots.get_mut(index).context(format!(
+            "No per-frame resource was found at index: {}",
+            index
+        ))
+    }
+
+    pub fn get(&self, index: usize) -> Result<&T> {
+        self.slots.get(index).context(format!(
+            "No per-frame resource was found at index: {}",
+            index
+        ))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.slots.iter()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameSyncBackend {
+    /// One fence per frame in flight
+    Fences,
+    /// A single timeline semaphore signaled with each frame's value
+    TimelineSemaphore,
+}
+
+struct PendingCapture {
+    readback: ReadbackBuffer,
+    format: vk::Format,
+    color_space: OutputColorSpace,
+    extent: vk::Extent2D,
+    path: PathBuf,
+    frame_value: u64,
+}
+
+impl PendingCapture {
+    fn save(&self) -> Result<PathBuf> {
+        let mut description = ImageDescription {
+            format: self.format,
+            width: self.extent.width,
+            height: self.extent.height,
+            depth: 1,
+            pixels: self.readback.read()?,
+            mip_levels: 1,
+            array_layers: 1,
+            mip_offsets: Vec::new(),
+            mip_filter: MipmapFilter::default(),
+            color_space: ColorSpace::of_format(self.format),
+        };
+        if self.color_space == OutputColorSpace::Hdr10 {
+            // PQ does not fit an eight bit PNG, so it is decoded into a linear EXR instead
+            let pixels = description
+                .rgba32f_pixels()?
+                .into_iter()
+                .flat_map(|pixel| {
+                    let color = decode_hdr10(pixel);
+                    vec![color[0], color[1], color[2], pixel[3]]
+                })
+                .flat_map(|channel| channel.to_le_bytes().to_vec())
+                .collect();
+            description.format = vk::Format::R32G32B32A32_SFLOAT;
+            description.pixels = pixels;
+            description.color_space = ColorSpace::Data;
+        }
+        description.save(&self.path)
+    }
+}
+
+/// Decodes a PQ encoded Rec. 2020 color into linear Rec. 709,
+/// scaled like scRGB so that 1.0 is 80 nits
+fn decode_hdr10(color: [f32; 4]) -> [f32; 3] {
+    let m1 = 2610.0 / 16384.0;
+    let m2 = 2523.0 / 4096.0 * 128.0;
+    let c1 = 3424.0 / 4096.0;
+    let c2 = 2413.0 / 4096.0 * 32.0;
+    let c3 = 2392.0 / 4096.0 * 32.0;
+    let linear = |encoded: f32| {
+        let power = encoded.max(0.0).powf(1.0 / m2);
+        let nits = ((power - c1).max(0.0) / (c2 - c3 * power)).powf(1.0 / m1) * 10000.0;
+        nits / 80.0
+    };
+    let [red, green, blue] = [linear(color[0]), linear(color[1]), linear(color[2])];
+    [
+        1.6605 * red - 0.5876 * green - 0.0728 * blue,
+        -0.1246 * red + 1.1329 * green - 0.0083 * blue,
+        -0.0182 * red - 0.1006 * green + 1.1187 * blue,
+    ]
+}
+
+/// Copies a presentable swapchain image into a readback buffer,
+/// leaving it in the layout it is presented from
+fn record_capture(
+    recorder: &mut FrameRecorder,
+    context: &Context,
+    image: vk::Image,
+    properties: &SwapchainProperties,
+    path: PathBuf,
+    frame_value: u64,
+) -> Result<(vk::CommandBuffer, PendingCapture)> {
+    let format = properties.surface_format.format;
+    let extent = properties.extent;
+    let size = (extent.width as vk::DeviceSize * extent.height as vk::DeviceSize)
+        .checked_mul(format_texel_size(format)? as _)
+        .context("Swapchain image is too large to capture!")?;
+    let readback = ReadbackBuffer::new(context.device.clone(), context.allocator.clone(), size)?;
+
+    let to_transfer_src = ImageLayoutTransitionBuilder::default()
+        .old_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+        .build()
+        .map_err(|error| anyhow!("{}", error))?;
+    let to_present = ImageLayoutTransitionBuilder::default()
+        .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+        .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+        .dst_access_mask(vk::AccessFlags::empty())
+        .src_stage_mask(vk::PipelineStageFlags::TRANSFER)
+        .dst_stage_mask(vk::PipelineStageFlags::BOTTOM_OF_PIPE)
+        .build()
+        .map_err(|error| anyhow!("{}", error))?;
+    let to_transfer_src = image_layout_barrier(image, &to_transfer_src)?;
+    let to_present = image_layout_barrier(image, &to_present)?;
+    let host_visible = readback.host_read_barrier()?;
+
+    let subresource = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .layer_count(1)
+        .build();
+    let region = vk::BufferImageCopy::builder()
+        .buffer_row_length(extent.width)
+        .buffer_image_height(extent.height)
+        .image_subresource(subresource)
+        .image_extent(vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        })
+        .build();
+    let copy_info = ImageToBufferCopyBuilder::default()
+        .source(image)
+        .destination(readback.handle())
+        .regions(vec![region])
+        .build()
+        .map_err(|error| anyhow!("{}", error))?;
+
+    let device = &context.device;
+    let command_buffer = recorder.record(|command_buffer| {
+        device.pipeline_barrier(command_buffer, &to_transfer_src);
+        device.copy_image_to_buffer(command_buffer, &copy_info);
+        device.pipeline_barrier(command_buffer, &to_present);
+        device.pipeline_barrier(command_buffer, &host_visible);
+        Ok(())
+    })?;
+
+    let capture = PendingCapture {
+        readback,
+        format,
+        color_space: properties.output_color_space(),
+        extent,
+        path,
+        frame_value,
+    };
+    Ok((command_buffer, capture))
+}
+
+/// Runs the command buffer after everything else submitted this frame
+fn append_to_final_batch(
+    mut batches: Vec<SubmitBatch>,
+    command_buffer: vk::CommandBuffer,
+) -> Vec<SubmitBatch> {
+    match batches.last_mut() {
+        Some(batch) => batch.command_buffers.push(command_buffer),
+        None => batches.push(SubmitBatch {
+            command_buffers: vec![command_buffer],
+            ..Default::default()
+        }),
+    }
+    batches
+}
+
+struct RetiredSwapchain {
+    _swapchain: Swapchain,
+    frame_value: u64,
 }
 
 pub struct FrameLock {
@@ -1,10 +1,12 @@
 use crate::vulkan::core::{
-    AllocatedImage, Device, Framebuffer, Image, ImageView, RenderPass, Sampler,
+    AllocatedImage, Device, Framebuffer, Image, ImageView, MemoryCategory, RenderPass, SamplerDesc,
+    SecondaryCommandPools,
 };
 use anyhow::{bail, ensure, Context, Result};
 use ash::vk;
 use petgraph::prelude::*;
-use std::{collections::HashMap, fmt, sync::Arc};
+use rayon::prelude::*;
+use std::{collections::HashMap, fmt, ops::Range, sync::Arc};
 use vk_mem::Allocator;
 
 #[derive(Default)]
@@ -13,7 +15,7 @@ pub struct RenderGraph {
     passes: HashMap<String, Pass>,
     images: HashMap<String, Box<dyn Image>>,
     image_views: HashMap<String, ImageView>,
-    samplers: HashMap<String, Sampler>,
+    samplers: HashMap<String, vk::Sampler>,
     framebuffers: HashMap<String, Framebuffer>,
 }
 
@@ -65,7 +67,7 @@ impl RenderGraph {
         self.process_images(device.clone(), allocator)?;
         self.process_passes(device.clone())?;
 
-        let default_sampler = create_default_sampler(device)?;
+        let default_sampler = device.sampler(&Self::default_sampler_desc())?;
         self.samplers.insert("default".to_string(), default_sampler);
 
         Ok(())
@@ -176,16 +178,65 @@ impl RenderGraph {
         mut action: impl FnMut(&Pass, vk::CommandBuffer) -> Result<()>,
     ) -> Result<()> {
         let pass = self.pass(name)?;
-        let framebuffer = if pass.presents_to_backbuffer {
-            self.framebuffer(&format!("backbuffer {}", backbuffer_image_index))
-        } else {
-            self.framebuffer(name)
-        }?;
+        let framebuffer = self.pass_framebuffer(pass, name, backbuffer_image_index)?;
         pass.execute(command_buffer, framebuffer.handle, |command_buffer| {
             action(pass, command_buffer)
         })
     }
 
+    /// Splits `item_count` items into ranges that are recorded into secondary command buffers
+    /// across the rayon thread pool, then executes them inside the pass.
+    /// Dynamic state is not inherited, so each range must set its own viewport and scissor.
+    pub fn execute_pass_parallel(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        name: &str,
+        backbuffer_image_index: usize,
+        secondary_pools: &SecondaryCommandPools,
+        item_count: usize,
+        action: impl Fn(&Pass, vk::CommandBuffer, Range<usize>) -> Result<()> + Sync,
+    ) -> Result<()> {
+        let pass = self.pass(name)?;
+        let framebuffer = self.pass_framebuffer(pass, name, backbuffer_image_index)?;
+
+        let threads = rayon::current_num_threads().max(1);
+        let chunk_size = ((item_count + threads - 1) / threads).max(1);
+        let ranges = (0..item_count)
+            .step_by(chunk_size)
+            .map(|start| start..(start + chunk_size).min(item_count))
+            .collect::<Vec<_>>();
+
+        let secondary_command_buffers = ranges
+            .into_par_iter()
+            .map(|range| {
+                secondary_pools.record(
+                    pass.render_pass.handle,
+                    framebuffer.handle,
+                    |command_buffer| action(pass, command_buffer, range.clone()),
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        pass.execute_secondary(
+            command_buffer,
+            framebuffer.handle,
+            &secondary_command_buffers,
+        )
+    }
+
+    fn pass_framebuffer(
+        &self,
+        pass: &Pass,
+        name: &str,
+        backbuffer_image_index: usize,
+    ) -> Result<&Framebuffer> {
+        if pass.presents_to_backbuffer {
+            self.framebuffer(&format!("backbuffer {}", backbuffer_image_index))
+        } else {
+            self.framebuffer(name)
+        }
+    }
+
     pub fn pass(&self, name: &str) -> Result<&Pass> {
         let error_message = format!(
             "Attempted to access renderpass with the key '{}' that was not found in the rendergraph",
@@ -238,12 +289,21 @@ impl RenderGraph {
         self.image_views.get(name).context(error_message)
     }
 
-    pub fn sampler(&self, name: &str) -> Result<&Sampler> {
+    /// Samplers come from the device's sampler cache, which owns them
+    pub fn sampler(&self, name: &str) -> Result<vk::Sampler> {
         let error_message = format!(
             "Attempted to access sampler with the key '{}' that was not found in the rendergraph",
             name
         );
-        self.samplers.get(name).context(error_message)
+        self.samplers.get(name).copied().context(error_message)
+    }
+
+    pub fn default_sampler_desc() -> SamplerDesc {
+        SamplerDesc {
+            max_anisotropy: 1,
+            border_color: vk::BorderColor::INT_OPAQUE_WHITE,
+            ..SamplerDesc::clamp_to_edge()
+        }
     }
 
     fn final_pass_node(&self) -> Result<(&PassNode, NodeIndex)> {
@@ -293,7 +353,7 @@ impl RenderGraph {
             return Ok(None);
         }
 
-        let allocated_image = image_node.allocate_image(allocator)?;
+        let allocated_image = image_node.allocate_image(device.clone(), allocator)?;
         let image_view = image_node.create_image_view(device, allocated_image.handle())?;
 
         Ok(Some((allocated_image, image_view)))
@@ -429,7 +489,11 @@ impl ImageNode {
             .build()
     }
 
-    pub fn allocate_image(&self, allocator: Arc<Allocator>) -> Result<AllocatedImage> {
+    pub fn allocate_image(
+        &self,
+        device: Arc<Device>,
+        allocator: Arc<Allocator>,
+    ) -> Result<AllocatedImage> {
         let extent = vk::Extent3D::builder()
             .width(self.extent.width)
             .height(self.extent.height)
@@ -454,7 +518,10 @@ impl ImageNode {
             ..Default::default()
         };
 
-        AllocatedImage::new(allocator, &allocation_create_info, &create_info)
+        let mut image =
+            AllocatedImage::new(device, allocator, &allocation_create_info, &create_info)?;
+        image.set_memory_category(MemoryCategory::RenderGraph);
+        Ok(image)
     }
 
     fn usage(&self) -> vk::ImageUsageFlags {
@@ -517,15 +584,31 @@ impl Pass {
         framebuffer: vk::Framebuffer,
         action: impl FnMut(vk::CommandBuffer) -> Result<()>,
     ) -> Result<()> {
+        self.render_pass
+            .record(command_buffer, self.begin_info(framebuffer), action)?;
+        Ok(())
+    }
+
+    pub fn execute_secondary(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        framebuffer: vk::Framebuffer,
+        secondary_command_buffers: &[vk::CommandBuffer],
+    ) -> Result<()> {
+        self.render_pass.execute_secondary(
+            command_buffer,
+            self.begin_info(framebuffer),
+            secondary_command_buffers,
+        )
+    }
+
+    fn begin_info(&self, framebuffer: vk::Framebuffer) -> vk::RenderPassBeginInfoBuilder {
         let render_area = vk::Rect2D::builder().extent(self.extent).build();
-        let begin_info = vk::RenderPassBeginInfo::builder()
+        vk::RenderPassBeginInfo::builder()
             .render_pass(self.render_pass.handle)
             .framebuffer(framebuffer)
             .render_area(render_area)
-            .clear_values(&self.clear_values);
-        self.render_pass
-            .record(command_buffer, begin_info, action)?;
-        Ok(())
+            .clear_values(&self.clear_values)
     }
 
     fn create_framebuffer(
@@ -650,23 +733,3 @@ impl PassBuilder {
             .build()
     }
 }
-
-fn create_default_sampler(device: Arc<Device>) -> Result<Sampler> {
-    let sampler_info = vk::SamplerCreateInfo::builder()
-        .mag_filter(vk::Filter::LINEAR)
-        .min_filter(vk::Filter::LINEAR)
-        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
-        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
-        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
-        .anisotropy_enable(true)
-        .max_anisotropy(1.0)
-        .border_color(vk::BorderColor::INT_OPAQUE_WHITE)
-        .unnormalized_coordinates(false)
-        .compare_enable(false)
-        .compare_op(vk::CompareOp::ALWAYS)
-        .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-        .mip_lod_bias(0.0)
-        .min_lod(0.0)
-        .max_lod(1.0);
-    Sampler::new(device, sampler_info)
-}
@@ -1,5 +1,16 @@
-pub use self::{buffer::*, image::*, shader::*};
+pub use self::{
+    buffer::*, compute::*, environment::*, geometry::*, image::*, mipmap::*, sampler::*, shader::*,
+    staging::*, texture_file::*, upload::*,
+};
 
 mod buffer;
+mod compute;
+mod environment;
+mod geometry;
 mod image;
+mod mipmap;
+mod sampler;
 mod shader;
+mod staging;
+mod texture_file;
+mod upload;
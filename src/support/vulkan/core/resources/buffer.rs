@@ -1,16 +1,24 @@
-use crate::vulkan::core::{BufferToBufferCopyBuilder, CommandPool};
-use anyhow::{anyhow, Result};
-use ash::{version::DeviceV1_0, vk};
-use std::sync::Arc;
+use crate::vulkan::core::{
+    BufferToBufferCopyBuilder, CommandPool, Context, Device, MemoryCategory, PipelineBarrier,
+    PipelineBarrierBuilder, UploadBatch,
+};
+use anyhow::{anyhow, bail, ensure, Context as AnyhowContext, Result};
+use ash::{
+    version::{DeviceV1_0, DeviceV1_2},
+    vk,
+};
+use std::{marker::PhantomData, sync::Arc};
 use vk_mem::Allocator;
 
 pub struct GpuBuffer {
     buffer: Buffer,
+    device: Arc<Device>,
     allocator: Arc<Allocator>,
 }
 
 impl GpuBuffer {
     pub fn new(
+        device: Arc<Device>,
         allocator: Arc<Allocator>,
         size: vk::DeviceSize,
         usage: vk::BufferUsageFlags,
@@ -21,14 +29,19 @@ impl GpuBuffer {
         };
         let buffer_create_info = vk::BufferCreateInfo::builder()
             .size(size)
-            .usage(vk::BufferUsageFlags::TRANSFER_DST | usage)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::TRANSFER_SRC | usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
         let buffer = Buffer::new(
+            device.clone(),
             allocator.clone(),
             &allocation_create_info,
             buffer_create_info,
         )?;
-        let gpu_buffer = Self { buffer, allocator };
+        let gpu_buffer = Self {
+            buffer,
+            device,
+            allocator,
+        };
         Ok(gpu_buffer)
     }
 
@@ -36,40 +49,136 @@ impl GpuBuffer {
         self.buffer.handle
     }
 
-    pub fn upload_data<T: Copy>(
+    /// Only valid for buffers created with `SHADER_DEVICE_ADDRESS` usage
+    pub fn device_address(&self) -> Result<vk::DeviceAddress> {
+        self.buffer.device_address()
+    }
+
+    /// Copies the whole buffer back to host memory and waits for the copy to finish
+    pub fn download<T: Copy>(&self, pool: &CommandPool) -> Result<Vec<T>> {
+        let readback = ReadbackBuffer::new(
+            self.device.clone(),
+            self.allocator.clone(),
+            self.buffer.size,
+        )?;
+
+        let region = vk::BufferCopy::builder().size(self.buffer.size).build();
+        let info = BufferToBufferCopyBuilder::default()
+            .source(self.buffer.handle)
+            .destination(readback.handle())
+            .regions(vec![region])
+            .build()
+            .map_err(|error| anyhow!("{}", error))?;
+        let host_visible = readback.host_read_barrier()?;
+
+        pool.execute_once(|command_buffer| {
+            self.device.copy_buffer_to_buffer(command_buffer, &info);
+            self.device.pipeline_barrier(command_buffer, &host_visible);
+            Ok(())
+        })?;
+
+        readback.read()
+    }
+
+    /// Stages the data, copies it into the buffer and waits for the copy to finish
+    pub fn upload_data<T: Copy>(&self, context: &Context, data: &[T], offset: usize) -> Result<()> {
+        let mut batch = UploadBatch::new(context)?;
+        self.record_upload(&mut batch, data, offset)?;
+        batch.submit()?.wait()
+    }
+
+    /// Records the upload into the batch instead of submitting it immediately
+    pub fn record_upload<T: Copy>(
         &self,
+        batch: &mut UploadBatch,
         data: &[T],
         offset: usize,
-        pool: &CommandPool,
     ) -> Result<()> {
-        let size = data.len() * std::mem::size_of::<T>();
-
-        let staging_buffer = CpuToGpuBuffer::staging_buffer(self.allocator.clone(), size as _)?;
-        staging_buffer.upload_data(data, 0)?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        let staged = batch.stage(data)?;
 
         let region = vk::BufferCopy::builder()
-            .size(size as _)
+            .src_offset(staged.offset)
+            .size(staged.size)
             .dst_offset(offset as _)
             .build();
 
         let info = BufferToBufferCopyBuilder::default()
-            .source(staging_buffer.buffer.handle)
+            .source(staged.buffer)
             .destination(self.buffer.handle)
             .regions(vec![region])
             .build()
             .map_err(|error| anyhow!("{}", error))?;
 
-        pool.copy_buffer_to_buffer(&info)?;
+        batch.copy_buffer_to_buffer(&info);
+        batch.transfer_buffer_ownership(self.buffer.handle, offset as _, staged.size)
+    }
 
-        Ok(())
+    pub fn storage_buffer(
+        device: Arc<Device>,
+        allocator: Arc<Allocator>,
+        size: vk::DeviceSize,
+    ) -> Result<Self> {
+        Self::new(
+            device,
+            allocator,
+            size,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+        )
+    }
+
+    /// Holds indirect draw or dispatch commands, which compute shaders may also write
+    pub fn indirect_buffer(
+        device: Arc<Device>,
+        allocator: Arc<Allocator>,
+        size: vk::DeviceSize,
+    ) -> Result<Self> {
+        Self::new(
+            device,
+            allocator,
+            size,
+            vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+        )
     }
 
-    pub fn vertex_buffer(allocator: Arc<Allocator>, size: vk::DeviceSize) -> Result<Self> {
-        Self::new(allocator, size, vk::BufferUsageFlags::VERTEX_BUFFER)
+    /// A buffer that shaders can access through its `device_address`
+    pub fn device_address_buffer(
+        context: &Context,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    ) -> Result<Self> {
+        ensure!(
+            context.enabled_features.buffer_device_address,
+            "Buffer device addresses are not supported by this device!"
+        );
+        Self::new(
+            context.device.clone(),
+            context.allocator.clone(),
+            size,
+            usage | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+        )
     }
 
-    pub fn index_buffer(allocator: Arc<Allocator>, size: vk::DeviceSize) -> Result<Self> {
-        Self::new(allocator, size, vk::BufferUsageFlags::INDEX_BUFFER)
+    pub fn vertex_buffer(
+        device: Arc<Device>,
+        allocator: Arc<Allocator>,
+        size: vk::DeviceSize,
+    ) -> Result<Self> {
+        let mut buffer = Self::new(device, allocator, size, vk::BufferUsageFlags::VERTEX_BUFFER)?;
+        buffer.buffer.set_memory_category(MemoryCategory::Geometry);
+        Ok(buffer)
+    }
+
+    pub fn index_buffer(
+        device: Arc<Device>,
+        allocator: Arc<Allocator>,
+        size: vk::DeviceSize,
+    ) -> Result<Self> {
+        let mut buffer = Self::new(device, allocator, size, vk::BufferUsageFlags::INDEX_BUFFER)?;
+        buffer.buffer.set_memory_category(MemoryCategory::Geometry);
+        Ok(buffer)
     }
 }
 
@@ -79,19 +188,27 @@ pub struct CpuToGpuBuffer {
 
 impl CpuToGpuBuffer {
     fn new(
+        device: Arc<Device>,
         allocator: Arc<Allocator>,
         size: vk::DeviceSize,
         usage: vk::BufferUsageFlags,
+        flags: vk_mem::AllocationCreateFlags,
     ) -> Result<Self> {
         let allocation_create_info = vk_mem::AllocationCreateInfo {
             usage: vk_mem::MemoryUsage::CpuToGpu,
+            flags,
             ..Default::default()
         };
         let buffer_create_info = vk::BufferCreateInfo::builder()
             .size(size)
             .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
-        let buffer = Buffer::new(allocator, &allocation_create_info, buffer_create_info)?;
+        let buffer = Buffer::new(
+            device,
+            allocator,
+            &allocation_create_info,
+            buffer_create_info,
+        )?;
         let cpu_to_gpu_buffer = Self { buffer };
         Ok(cpu_to_gpu_buffer)
     }
@@ -100,12 +217,101 @@ impl CpuToGpuBuffer {
         self.buffer.handle
     }
 
-    pub fn staging_buffer(allocator: Arc<Allocator>, size: vk::DeviceSize) -> Result<Self> {
-        Self::new(allocator, size, vk::BufferUsageFlags::TRANSFER_SRC)
+    pub fn staging_buffer(
+        device: Arc<Device>,
+        allocator: Arc<Allocator>,
+        size: vk::DeviceSize,
+    ) -> Result<Self> {
+        let mut buffer = Self::new(
+            device,
+            allocator,
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk_mem::AllocationCreateFlags::NONE,
+        )?;
+        buffer.buffer.set_memory_category(MemoryCategory::Staging);
+        Ok(buffer)
+    }
+
+    /// A staging buffer that stays mapped for its whole lifetime
+    pub fn persistent_staging_buffer(
+        device: Arc<Device>,
+        allocator: Arc<Allocator>,
+        size: vk::DeviceSize,
+    ) -> Result<Self> {
+        let mut buffer = Self::new(
+            device,
+            allocator,
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk_mem::AllocationCreateFlags::MAPPED,
+        )?;
+        buffer.buffer.set_memory_category(MemoryCategory::Staging);
+        Ok(buffer)
     }
 
-    pub fn uniform_buffer(allocator: Arc<Allocator>, size: vk::DeviceSize) -> Result<Self> {
-        Self::new(allocator, size, vk::BufferUsageFlags::UNIFORM_BUFFER)
+    pub fn uniform_buffer(
+        device: Arc<Device>,
+        allocator: Arc<Allocator>,
+        size: vk::DeviceSize,
+    ) -> Result<Self> {
+        Self::new(
+            device,
+            allocator,
+            size,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk_mem::AllocationCreateFlags::NONE,
+        )
+    }
+
+    /// A uniform buffer that stays mapped for its whole lifetime, for data written every frame
+    pub fn persistent_uniform_buffer(
+        device: Arc<Device>,
+        allocator: Arc<Allocator>,
+        size: vk::DeviceSize,
+    ) -> Result<Self> {
+        Self::new(
+            device,
+            allocator,
+            size,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk_mem::AllocationCreateFlags::MAPPED,
+        )
+    }
+
+    /// Host written storage, such as per instance data that changes every frame
+    pub fn storage_buffer(
+        device: Arc<Device>,
+        allocator: Arc<Allocator>,
+        size: vk::DeviceSize,
+    ) -> Result<Self> {
+        Self::new(
+            device,
+            allocator,
+            size,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk_mem::AllocationCreateFlags::NONE,
+        )
+    }
+
+    /// Indirect draw or dispatch commands written by the host
+    pub fn indirect_buffer(
+        device: Arc<Device>,
+        allocator: Arc<Allocator>,
+        size: vk::DeviceSize,
+    ) -> Result<Self> {
+        Self::new(
+            device,
+            allocator,
+            size,
+            vk::BufferUsageFlags::INDIRECT_BUFFER,
+            vk_mem::AllocationCreateFlags::NONE,
+        )
+    }
+
+    /// Only valid for buffers created with `SHADER_DEVICE_ADDRESS` usage
+    pub fn device_address(&self) -> Result<vk::DeviceAddress> {
+        self.buffer.device_address()
     }
 
     pub fn upload_data<T>(&self, data: &[T], offset: usize) -> Result<()> {
@@ -124,18 +330,46 @@ impl CpuToGpuBuffer {
         offset: usize,
         alignment: vk::DeviceSize,
     ) -> Result<()> {
+        let stride =
+            (std::mem::size_of::<T>() as vk::DeviceSize + alignment - 1) & !(alignment - 1);
+        let size = stride as usize * data.len();
+        if offset + size > self.buffer.size as usize {
+            bail!(
+                "Aligned upload of {} bytes at offset {} overflows a buffer of {} bytes!",
+                size,
+                offset,
+                self.buffer.size
+            );
+        }
         let data_pointer = self.map_memory()?;
-        let size = self.buffer.allocation_info.get_size();
         unsafe {
             let data_pointer = data_pointer.add(offset);
             let mut align = ash::util::Align::new(data_pointer as _, alignment, size as _);
             align.copy_from_slice(data);
         }
-        self.buffer.flush(0, size);
+        self.buffer.flush(offset, size);
         self.unmap_memory();
         Ok(())
     }
 
+    pub fn flush(&self, offset: usize, size: usize) {
+        self.buffer.flush(offset, size);
+    }
+
+    /// Writes through the persistent mapping and flushes the written range
+    pub fn write_mapped<T: Copy>(&self, data: &[T], offset: usize) -> Result<()> {
+        let data_pointer = self.buffer.allocation_info.get_mapped_data();
+        if data_pointer.is_null() {
+            bail!("Buffer is not persistently mapped!");
+        }
+        let size = data.len() * std::mem::size_of::<T>();
+        unsafe {
+            (data.as_ptr() as *const u8).copy_to_nonoverlapping(data_pointer.add(offset), size);
+        }
+        self.buffer.flush(offset, size);
+        Ok(())
+    }
+
     pub fn map_memory(&self) -> vk_mem::error::Result<*mut u8> {
         self.buffer.allocator.map_memory(&self.buffer.allocation)
     }
@@ -145,27 +379,305 @@ impl CpuToGpuBuffer {
     }
 }
 
+/// A host visible buffer of `T` values that stays mapped for its whole lifetime.
+///
+/// Writes through `as_slice_mut` must be followed by `flush_range`.
+/// Flushing is skipped by the allocator for host coherent memory.
+pub struct MappedBuffer<T> {
+    buffer: Buffer,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy> MappedBuffer<T> {
+    pub fn new(
+        device: Arc<Device>,
+        allocator: Arc<Allocator>,
+        len: usize,
+        usage: vk::BufferUsageFlags,
+    ) -> Result<Self> {
+        ensure!(len > 0, "Mapped buffers must hold at least one value!");
+        let size = len
+            .checked_mul(std::mem::size_of::<T>())
+            .context("Mapped buffer size overflows!")?;
+        ensure!(size > 0, "Mapped buffers cannot hold zero-sized values!");
+        let allocation_create_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::CpuToGpu,
+            flags: vk_mem::AllocationCreateFlags::MAPPED,
+            ..Default::default()
+        };
+        let buffer_create_info = vk::BufferCreateInfo::builder()
+            .size(size as _)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = Buffer::new(
+            device,
+            allocator,
+            &allocation_create_info,
+            buffer_create_info,
+        )?;
+        if buffer.allocation_info.get_mapped_data().is_null() {
+            bail!("Failed to persistently map buffer memory!");
+        }
+        Ok(Self {
+            buffer,
+            len,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn uniform_buffer(
+        device: Arc<Device>,
+        allocator: Arc<Allocator>,
+        len: usize,
+    ) -> Result<Self> {
+        Self::new(device, allocator, len, vk::BufferUsageFlags::UNIFORM_BUFFER)
+    }
+
+    pub fn vertex_buffer(
+        device: Arc<Device>,
+        allocator: Arc<Allocator>,
+        len: usize,
+    ) -> Result<Self> {
+        Self::new(device, allocator, len, vk::BufferUsageFlags::VERTEX_BUFFER)
+    }
+
+    pub fn handle(&self) -> vk::Buffer {
+        self.buffer.handle
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.data_pointer(), self.len) }
+    }
+
+    pub fn as_slice_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.data_pointer(), self.len) }
+    }
+
+    /// Writes the values starting at `index` and flushes only the written range
+    pub fn write(&mut self, index: usize, values: &[T]) -> Result<()> {
+        self.check_range(index, values.len())?;
+        self.as_slice_mut()[index..index + values.len()].copy_from_slice(values);
+        self.flush_range(index, values.len())
+    }
+
+    /// Makes host writes to `count` values starting at `index` visible to the device
+    pub fn flush_range(&self, index: usize, count: usize) -> Result<()> {
+        self.check_range(index, count)?;
+        let size = std::mem::size_of::<T>();
+        self.buffer.flush(index * size, count * size);
+        Ok(())
+    }
+
+    fn check_range(&self, index: usize, count: usize) -> Result<()> {
+        if index + count > self.len {
+            bail!(
+                "Range {}..{} is out of bounds for a mapped buffer of length {}!",
+                index,
+                index + count,
+                self.len
+            );
+        }
+        Ok(())
+    }
+
+    fn data_pointer(&self) -> *mut T {
+        self.buffer.allocation_info.get_mapped_data() as *mut T
+    }
+}
+
+/// A uniform buffer split into one region per frame in flight.
+///
+/// Values are pushed into the region belonging to the current frame
+/// and bound with the returned dynamic offset,
+/// so writes never touch data the GPU may still be reading.
+pub struct DynamicUniformBuffer<T> {
+    buffer: CpuToGpuBuffer,
+    alignment: vk::DeviceSize,
+    capacity: usize,
+    frames_in_flight: usize,
+    frame_index: usize,
+    cursor: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy> DynamicUniformBuffer<T> {
+    pub fn new(context: &Context, capacity: usize, frames_in_flight: usize) -> Result<Self> {
+        let alignment = context.dynamic_alignment_of::<T>();
+        let size = alignment * (capacity * frames_in_flight) as vk::DeviceSize;
+        let buffer = CpuToGpuBuffer::persistent_uniform_buffer(
+            context.device.clone(),
+            context.allocator.clone(),
+            size,
+        )?;
+        Ok(Self {
+            buffer,
+            alignment,
+            capacity,
+            frames_in_flight,
+            frame_index: 0,
+            cursor: 0,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn handle(&self) -> vk::Buffer {
+        self.buffer.handle()
+    }
+
+    /// The range to use for the dynamic uniform buffer descriptor
+    pub fn alignment(&self) -> vk::DeviceSize {
+        self.alignment
+    }
+
+    /// Starts writing into the region owned by the given frame in flight
+    pub fn begin_frame(&mut self, frame_index: usize) {
+        self.frame_index = frame_index % self.frames_in_flight;
+        self.cursor = 0;
+    }
+
+    /// Writes a value and returns its dynamic offset
+    pub fn push(&mut self, value: &T) -> Result<u32> {
+        if self.cursor >= self.capacity {
+            bail!(
+                "Dynamic uniform buffer is full! Capacity per frame is: {}",
+                self.capacity
+            );
+        }
+        let offset =
+            (self.frame_index * self.capacity + self.cursor) as vk::DeviceSize * self.alignment;
+        self.buffer
+            .write_mapped(std::slice::from_ref(value), offset as _)?;
+        self.cursor += 1;
+        Ok(offset as u32)
+    }
+}
+
+/// Host visible memory that the GPU copies results back into
+pub struct ReadbackBuffer {
+    buffer: Buffer,
+}
+
+impl ReadbackBuffer {
+    pub fn new(
+        device: Arc<Device>,
+        allocator: Arc<Allocator>,
+        size: vk::DeviceSize,
+    ) -> Result<Self> {
+        let allocation_create_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuToCpu,
+            flags: vk_mem::AllocationCreateFlags::MAPPED,
+            ..Default::default()
+        };
+        let buffer_create_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = Buffer::new(
+            device,
+            allocator,
+            &allocation_create_info,
+            buffer_create_info,
+        )?;
+        Ok(Self { buffer })
+    }
+
+    pub fn handle(&self) -> vk::Buffer {
+        self.buffer.handle
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.buffer.size
+    }
+
+    /// Makes transfer writes into this buffer visible to the host.
+    /// Record it after the copy and before the submission is waited on.
+    pub fn host_read_barrier(&self) -> Result<PipelineBarrier> {
+        let barrier = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(self.buffer.handle)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build();
+        PipelineBarrierBuilder::default()
+            .src_stage_mask(vk::PipelineStageFlags::TRANSFER)
+            .dst_stage_mask(vk::PipelineStageFlags::HOST)
+            .buffer_memory_barriers(vec![barrier])
+            .image_memory_barriers(Vec::new())
+            .build()
+            .map_err(|error| anyhow!("{}", error))
+    }
+
+    /// Reads back the buffer contents once the GPU has finished writing them
+    pub fn read<T: Copy>(&self) -> Result<Vec<T>> {
+        ensure!(
+            std::mem::size_of::<T>() > 0,
+            "Readback buffers cannot be read as zero-sized values!"
+        );
+        let data_pointer = self.buffer.allocation_info.get_mapped_data();
+        if data_pointer.is_null() {
+            bail!("Readback buffer is not mapped!");
+        }
+        let size = self.buffer.size as usize;
+        self.buffer.invalidate(0, size);
+
+        let count = size / std::mem::size_of::<T>();
+        let mut data = Vec::<T>::with_capacity(count);
+        unsafe {
+            (data_pointer as *const u8).copy_to_nonoverlapping(
+                data.as_mut_ptr() as *mut u8,
+                count * std::mem::size_of::<T>(),
+            );
+            data.set_len(count);
+        }
+        Ok(data)
+    }
+}
+
 pub struct Buffer {
     pub handle: vk::Buffer,
+    pub size: vk::DeviceSize,
+    pub usage: vk::BufferUsageFlags,
+    category: MemoryCategory,
     pub allocation_info: vk_mem::AllocationInfo,
     allocation: vk_mem::Allocation,
     allocator: Arc<Allocator>,
+    device: Arc<Device>,
 }
 
 impl Buffer {
     pub fn new(
+        device: Arc<Device>,
         allocator: Arc<Allocator>,
         allocation_create_info: &vk_mem::AllocationCreateInfo,
         buffer_create_info: vk::BufferCreateInfoBuilder,
     ) -> Result<Self> {
         let (handle, allocation, allocation_info) =
             allocator.create_buffer(&buffer_create_info, allocation_create_info)?;
+        device
+            .memory_usage()
+            .allocate(MemoryCategory::Other, allocation_info.get_size() as _);
 
         let buffer = Self {
             handle,
+            size: buffer_create_info.size,
+            usage: buffer_create_info.usage,
+            category: MemoryCategory::Other,
             allocation_info,
             allocation,
             allocator,
+            device,
         };
 
         Ok(buffer)
@@ -175,11 +687,43 @@ impl Buffer {
         self.allocator
             .flush_allocation(&self.allocation, offset, size);
     }
+
+    /// Moves the allocation to the category it is accounted under
+    pub fn set_memory_category(&mut self, category: MemoryCategory) {
+        let size = self.allocation_info.get_size() as vk::DeviceSize;
+        let memory_usage = self.device.memory_usage();
+        memory_usage.free(self.category, size);
+        memory_usage.allocate(category, size);
+        self.category = category;
+    }
+
+    pub fn device_address(&self) -> Result<vk::DeviceAddress> {
+        ensure!(
+            self.usage
+                .contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS),
+            "Buffer was not created with device address usage!"
+        );
+        let info = vk::BufferDeviceAddressInfo::builder().buffer(self.handle);
+        let address = unsafe { self.device.handle.get_buffer_device_address(&info) };
+        Ok(address)
+    }
+
+    pub fn invalidate(&self, offset: usize, size: usize) {
+        self.allocator
+            .invalidate_allocation(&self.allocation, offset, size);
+    }
 }
 
 impl Drop for Buffer {
     fn drop(&mut self) {
-        self.allocator.destroy_buffer(self.handle, &self.allocation);
+        self.device
+            .memory_usage()
+            .free(self.category, self.allocation_info.get_size() as _);
+        let handle = self.handle;
+        let allocation = self.allocation;
+        let allocator = self.allocator.clone();
+        self.device
+            .defer_destruction(move |_| allocator.destroy_buffer(handle, &allocation));
     }
 }
 
@@ -188,17 +732,21 @@ pub struct GeometryBuffer {
     pub index_buffer: Option<GpuBuffer>,
     pub vertex_buffer_size: vk::DeviceSize,
     pub index_buffer_size: Option<vk::DeviceSize>,
+    /// Defaults to 32-bit indices
+    pub index_type: vk::IndexType,
 }
 
 impl GeometryBuffer {
     pub fn new(
+        device: Arc<Device>,
         allocator: Arc<Allocator>,
         vertex_buffer_size: vk::DeviceSize,
         index_buffer_size: Option<vk::DeviceSize>,
     ) -> Result<Self> {
-        let vertex_buffer = GpuBuffer::vertex_buffer(allocator.clone(), vertex_buffer_size)?;
+        let vertex_buffer =
+            GpuBuffer::vertex_buffer(device.clone(), allocator.clone(), vertex_buffer_size)?;
         let index_buffer = if let Some(index_buffer_size) = index_buffer_size {
-            let index_buffer = GpuBuffer::index_buffer(allocator, index_buffer_size)?;
+            let index_buffer = GpuBuffer::index_buffer(device, allocator, index_buffer_size)?;
             Some(index_buffer)
         } else {
             None
@@ -208,11 +756,11 @@ impl GeometryBuffer {
             index_buffer,
             vertex_buffer_size,
             index_buffer_size,
+            index_type: vk::IndexType::UINT32,
         };
         Ok(geometry_buffer)
     }
 
-    /// Assumes 32-bit index buffers
     pub fn bind(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) -> Result<()> {
         let offsets = [0];
         let vertex_buffers = [self.vertex_buffer.handle()];
@@ -223,7 +771,7 @@ impl GeometryBuffer {
                     command_buffer,
                     index_buffer.handle(),
                     0,
-                    vk::IndexType::UINT32,
+                    self.index_type,
                 );
             }
         };
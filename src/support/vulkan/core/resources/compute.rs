@@ -0,0 +1,201 @@
+use crate::vulkan::core::{
+    DescriptorPool, DescriptorSetLayout, Device, Pipeline, PipelineLayout, Shader, ShaderSet,
+    UploadBatch,
+};
+use anyhow::Result;
+use ash::{version::DeviceV1_0, vk};
+use std::sync::Arc;
+
+/// An image bound to a compute kernel
+#[derive(Debug, Clone, Copy)]
+pub enum KernelBinding {
+    /// Read through a sampler in `SHADER_READ_ONLY_OPTIMAL`
+    Sampled(vk::ImageView, vk::Sampler),
+    /// Read with texel fetches in `SHADER_READ_ONLY_OPTIMAL`
+    Texture(vk::ImageView),
+    /// Written in the `GENERAL` layout
+    Storage(vk::ImageView),
+}
+
+impl KernelBinding {
+    fn descriptor_type(&self) -> vk::DescriptorType {
+        match self {
+            KernelBinding::Sampled(..) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            KernelBinding::Texture(_) => vk::DescriptorType::SAMPLED_IMAGE,
+            KernelBinding::Storage(_) => vk::DescriptorType::STORAGE_IMAGE,
+        }
+    }
+}
+
+/// A compute pipeline whose bindings are sampled and storage images, numbered in order,
+/// for one-off image processing recorded into an upload batch
+pub struct ComputeKernel {
+    pipeline: Pipeline,
+    pipeline_layout: PipelineLayout,
+    descriptor_set_layout: DescriptorSetLayout,
+    binding_types: Vec<vk::DescriptorType>,
+    push_constant_size: u32,
+    device: Arc<Device>,
+}
+
+impl ComputeKernel {
+    pub fn new(
+        device: Arc<Device>,
+        shader_path: &str,
+        binding_types: &[vk::DescriptorType],
+        push_constant_size: u32,
+    ) -> Result<Self> {
+        let bindings = binding_types
+            .iter()
+            .enumerate()
+            .map(|(index, descriptor_type)| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(index as _)
+                    .descriptor_type(*descriptor_type)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let create_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+        let descriptor_set_layout = DescriptorSetLayout::new(device.clone(), create_info)?;
+
+        let set_layouts = [descriptor_set_layout.handle];
+        let push_constant_ranges = [vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(push_constant_size)
+            .build()];
+        let mut layout_create_info =
+            vk::PipelineLayoutCreateInfo::builder().set_layouts(&set_layouts);
+        if push_constant_size > 0 {
+            layout_create_info = layout_create_info.push_constant_ranges(&push_constant_ranges);
+        }
+        let pipeline_layout = PipelineLayout::new(device.clone(), *layout_create_info)?;
+
+        let shader = Shader::from_file(shader_path, device.clone())?;
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader.module)
+            .name(ShaderSet::entry_point_name()?);
+        let create_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage.build())
+            .layout(pipeline_layout.handle);
+        let pipeline = Pipeline::new_compute(device.clone(), create_info)?;
+
+        Ok(Self {
+            pipeline,
+            pipeline_layout,
+            descriptor_set_layout,
+            binding_types: binding_types.to_vec(),
+            push_constant_size,
+            device,
+        })
+    }
+
+    /// Records a dispatch on the graphics queue.
+    /// The descriptors it uses are kept alive by the batch until the upload finishes.
+    pub fn dispatch(
+        &self,
+        batch: &mut UploadBatch,
+        bindings: &[KernelBinding],
+        push_constants: &[u8],
+        group_counts: [u32; 3],
+    ) -> Result<()> {
+        let descriptor_pool = self.descriptor_pool()?;
+        let descriptor_set =
+            descriptor_pool.allocate_descriptor_sets(self.descriptor_set_layout.handle, 1)?[0];
+        self.update_descriptor_set(descriptor_set, bindings);
+
+        let device = &self.device.handle;
+        let command_buffer = batch.graphics_command_buffer();
+        self.pipeline.bind(device, command_buffer);
+        unsafe {
+            if self.push_constant_size > 0 {
+                device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout.handle,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    push_constants,
+                );
+            }
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout.handle,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            device.cmd_dispatch(
+                command_buffer,
+                group_counts[0],
+                group_counts[1],
+                group_counts[2],
+            );
+        }
+
+        batch.retain(descriptor_pool);
+        Ok(())
+    }
+
+    fn descriptor_pool(&self) -> Result<DescriptorPool> {
+        let pool_sizes = self
+            .binding_types
+            .iter()
+            .map(|descriptor_type| {
+                vk::DescriptorPoolSize::builder()
+                    .ty(*descriptor_type)
+                    .descriptor_count(1)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1);
+
+        DescriptorPool::new(self.device.clone(), pool_info)
+    }
+
+    fn update_descriptor_set(&self, descriptor_set: vk::DescriptorSet, bindings: &[KernelBinding]) {
+        let image_infos = bindings
+            .iter()
+            .map(|binding| {
+                let image_info = match binding {
+                    KernelBinding::Sampled(view, sampler) => vk::DescriptorImageInfo::builder()
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .image_view(*view)
+                        .sampler(*sampler),
+                    KernelBinding::Texture(view) => vk::DescriptorImageInfo::builder()
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .image_view(*view),
+                    KernelBinding::Storage(view) => vk::DescriptorImageInfo::builder()
+                        .image_layout(vk::ImageLayout::GENERAL)
+                        .image_view(*view),
+                };
+                [image_info.build()]
+            })
+            .collect::<Vec<_>>();
+
+        let writes = bindings
+            .iter()
+            .zip(image_infos.iter())
+            .enumerate()
+            .map(|(index, (binding, image_info))| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(index as _)
+                    .descriptor_type(binding.descriptor_type())
+                    .image_info(image_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        unsafe { self.device.handle.update_descriptor_sets(&writes, &[]) }
+    }
+}
+
+/// The number of 8x8 work groups that cover an image of the given size
+pub fn work_group_count(size: u32) -> u32 {
+    (size + 7) / 8
+}
@@ -0,0 +1,453 @@
+use crate::vulkan::{
+    byte_slice_from,
+    core::{
+        work_group_count, CommandPool, ComputeKernel, Context, Cubemap, ImageDescription,
+        KernelBinding, SamplerDesc, Texture, UploadBatch,
+    },
+};
+use anyhow::{ensure, Result};
+use ash::vk;
+use derive_builder::Builder;
+use log::{info, warn};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+#[derive(Builder, Clone, Debug)]
+#[builder(default)]
+pub struct IblSettings {
+    /// Width of a face of the diffuse irradiance cubemap
+    pub irradiance_size: u32,
+
+    /// Angle in radians between the samples of the irradiance convolution
+    pub irradiance_sample_delta: f32,
+
+    /// Width of a face of the base level of the prefiltered specular cubemap
+    pub prefiltered_size: u32,
+
+    /// Mip levels of the prefiltered cubemap. Roughness goes from 0 at the base level to 1 at the last.
+    /// Clamped to the levels that the size allows.
+    pub prefiltered_mip_levels: u32,
+
+    pub prefiltered_sample_count: u32,
+    pub brdf_lut_size: u32,
+    pub brdf_lut_sample_count: u32,
+}
+
+impl IblSettings {
+    /// The prefiltered mip levels, clamped to what the size allows
+    pub fn prefiltered_mip_levels(&self) -> u32 {
+        let size = self.prefiltered_size;
+        self.prefiltered_mip_levels
+            .min(ImageDescription::calculate_mip_levels(size, size))
+            .max(1)
+    }
+}
+
+impl Default for IblSettings {
+    fn default() -> Self {
+        Self {
+            irradiance_size: 32,
+            irradiance_sample_delta: 0.025,
+            prefiltered_size: 128,
+            prefiltered_mip_levels: 5,
+            prefiltered_sample_count: 1024,
+            brdf_lut_size: 512,
+            brdf_lut_sample_count: 1024,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+struct IrradiancePushConstantBlock {
+    sample_delta: f32,
+    source_lod: f32,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+struct PrefilterPushConstantBlock {
+    roughness: f32,
+    sample_count: u32,
+    environment_size: f32,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+struct BrdfLutPushConstantBlock {
+    sample_count: u32,
+}
+
+/// The maps that split sum image based lighting samples,
+/// precomputed on the GPU from an environment cubemap
+pub struct ImageBasedLighting {
+    /// Cosine weighted irradiance for diffuse lighting, sampled with the surface normal
+    pub irradiance: Cubemap,
+    /// GGX prefiltered radiance for specular lighting,
+    /// sampled with the reflection vector at the level of the surface roughness
+    pub prefiltered: Cubemap,
+    /// The scale and bias applied to F0, indexed by N dot V and roughness
+    pub brdf_lut: Texture,
+}
+
+impl ImageBasedLighting {
+    pub const IRRADIANCE_SHADER_PATH: &'static str =
+        "assets/shaders/environment/irradiance.comp.spv";
+    pub const PREFILTER_SHADER_PATH: &'static str = "assets/shaders/environment/prefilter.comp.spv";
+    pub const BRDF_LUT_SHADER_PATH: &'static str = "assets/shaders/environment/brdf_lut.comp.spv";
+
+    const IRRADIANCE_FILE: &'static str = "irradiance.ktx2";
+    const PREFILTERED_FILE: &'static str = "prefiltered.ktx2";
+    const BRDF_LUT_FILE: &'static str = "brdf_lut.ktx2";
+    const CACHE_KEY_FILE: &'static str = "cache_key";
+
+    const CUBEMAP_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+    const BRDF_LUT_FORMAT: vk::Format = vk::Format::R16G16_SFLOAT;
+
+    // The irradiance convolution reads a level of the environment about this wide,
+    // since sampling the base level at a coarse angle misses small, bright sources
+    const IRRADIANCE_SOURCE_SIZE: f32 = 64.0;
+
+    /// Precomputes every map in a single submission and waits for it to finish.
+    /// The environment should have mipmaps, which prefiltering reads to avoid aliasing.
+    pub fn new(context: &Context, environment: &Cubemap, settings: &IblSettings) -> Result<Self> {
+        Self::ensure_storage_supported(context, Self::CUBEMAP_FORMAT)?;
+        Self::ensure_storage_supported(context, Self::BRDF_LUT_FORMAT)?;
+
+        let device = context.device.clone();
+        let irradiance_kernel = ComputeKernel::new(
+            device.clone(),
+            Self::IRRADIANCE_SHADER_PATH,
+            &[
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::DescriptorType::STORAGE_IMAGE,
+            ],
+            std::mem::size_of::<IrradiancePushConstantBlock>() as _,
+        )?;
+        let prefilter_kernel = ComputeKernel::new(
+            device.clone(),
+            Self::PREFILTER_SHADER_PATH,
+            &[
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::DescriptorType::STORAGE_IMAGE,
+            ],
+            std::mem::size_of::<PrefilterPushConstantBlock>() as _,
+        )?;
+        let brdf_lut_kernel = ComputeKernel::new(
+            device,
+            Self::BRDF_LUT_SHADER_PATH,
+            &[vk::DescriptorType::STORAGE_IMAGE],
+            std::mem::size_of::<BrdfLutPushConstantBlock>() as _,
+        )?;
+        // Prefiltering picks between every level of the environment by sample density
+        let sampler = context.device.sampler(&SamplerDesc::clamp_to_edge())?;
+
+        let mut batch = UploadBatch::new(context)?;
+        let irradiance = Self::record_irradiance(
+            &mut batch,
+            context,
+            &irradiance_kernel,
+            environment,
+            sampler,
+            settings,
+        )?;
+        let prefiltered = Self::record_prefiltered(
+            &mut batch,
+            context,
+            &prefilter_kernel,
+            environment,
+            sampler,
+            settings,
+        )?;
+        let brdf_lut = Self::record_brdf_lut(&mut batch, context, &brdf_lut_kernel, settings)?;
+        batch.submit()?.wait()?;
+
+        Ok(Self {
+            irradiance,
+            prefiltered,
+            brdf_lut,
+        })
+    }
+
+    /// Loads the maps from the cache directory, or generates them and writes them there.
+    /// The cache is regenerated whenever the environment or the settings change.
+    pub fn load_or_generate<P: AsRef<Path>>(
+        context: &Context,
+        environment: &Cubemap,
+        settings: &IblSettings,
+        cache_directory: P,
+    ) -> Result<Self> {
+        let directory = cache_directory.as_ref();
+        let key = Self::cache_key(context, environment, settings)?;
+        match Self::load(context, settings, key, directory) {
+            Ok(lighting) => return Ok(lighting),
+            Err(error) => info!(
+                "Generating image based lighting, the cache in {} is unusable: {}",
+                directory.display(),
+                error
+            ),
+        }
+        let lighting = Self::new(context, environment, settings)?;
+        if let Err(error) = lighting.save(context, key, directory) {
+            warn!(
+                "Failed to cache image based lighting in {}: {}",
+                directory.display(),
+                error
+            );
+        }
+        Ok(lighting)
+    }
+
+    /// Identifies the maps generated from the environment's pixels with the settings
+    pub fn cache_key(
+        context: &Context,
+        environment: &Cubemap,
+        settings: &IblSettings,
+    ) -> Result<u64> {
+        let pool = Self::transient_pool(context)?;
+        let source = environment.image.download_levels(
+            context,
+            &pool,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )?;
+
+        // The default hasher is only stable within a build, which at worst regenerates the cache
+        let mut hasher = DefaultHasher::new();
+        source.format.as_raw().hash(&mut hasher);
+        (source.width, source.height, source.mip_levels).hash(&mut hasher);
+        source.pixels.hash(&mut hasher);
+        settings.irradiance_size.hash(&mut hasher);
+        settings.irradiance_sample_delta.to_bits().hash(&mut hasher);
+        settings.prefiltered_size.hash(&mut hasher);
+        settings.prefiltered_mip_levels().hash(&mut hasher);
+        settings.prefiltered_sample_count.hash(&mut hasher);
+        settings.brdf_lut_size.hash(&mut hasher);
+        settings.brdf_lut_sample_count.hash(&mut hasher);
+        Ok(hasher.finish())
+    }
+
+    /// Loads maps written by `save`, checking that they were saved with the same key
+    /// and that their sizes and formats match the settings
+    pub fn load<P: AsRef<Path>>(
+        context: &Context,
+        settings: &IblSettings,
+        key: u64,
+        directory: P,
+    ) -> Result<Self> {
+        let directory = directory.as_ref();
+        let cached_key = std::fs::read_to_string(directory.join(Self::CACHE_KEY_FILE))?;
+        ensure!(
+            cached_key.trim() == format!("{:016x}", key),
+            "Cached maps were generated from a different environment or settings!"
+        );
+
+        let irradiance = ImageDescription::from_ktx2(directory.join(Self::IRRADIANCE_FILE))?;
+        let prefiltered = ImageDescription::from_ktx2(directory.join(Self::PREFILTERED_FILE))?;
+        let brdf_lut = ImageDescription::from_ktx2(directory.join(Self::BRDF_LUT_FILE))?;
+
+        let expected = [
+            (
+                &irradiance,
+                settings.irradiance_size,
+                1,
+                Self::CUBEMAP_FORMAT,
+            ),
+            (
+                &prefiltered,
+                settings.prefiltered_size,
+                settings.prefiltered_mip_levels(),
+                Self::CUBEMAP_FORMAT,
+            ),
+            (&brdf_lut, settings.brdf_lut_size, 1, Self::BRDF_LUT_FORMAT),
+        ];
+        for (description, size, mip_levels, format) in expected.iter() {
+            ensure!(
+                description.format == *format,
+                "Cached map has format {:?}, but {:?} is expected!",
+                description.format,
+                format
+            );
+            ensure!(
+                description.width == *size
+                    && description.height == *size
+                    && description.mip_levels == *mip_levels,
+                "Cached map is {}x{} with {} mip levels, but {}x{} with {} are expected!",
+                description.width,
+                description.height,
+                description.mip_levels,
+                size,
+                size,
+                mip_levels
+            );
+        }
+
+        Ok(Self {
+            irradiance: Cubemap::new(context, &irradiance)?,
+            prefiltered: Cubemap::new(context, &prefiltered)?,
+            brdf_lut: Texture::new(context, &brdf_lut)?,
+        })
+    }
+
+    /// Writes the maps to the directory as KTX2 files next to the key, creating it if needed
+    pub fn save<P: AsRef<Path>>(&self, context: &Context, key: u64, directory: P) -> Result<()> {
+        let directory = directory.as_ref();
+        std::fs::create_dir_all(directory)?;
+
+        // The key is removed first and written last,
+        // so that an interrupted save is never mistaken for a complete cache
+        let key_path = directory.join(Self::CACHE_KEY_FILE);
+        if key_path.exists() {
+            std::fs::remove_file(&key_path)?;
+        }
+
+        let pool = Self::transient_pool(context)?;
+        let layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        self.irradiance
+            .image
+            .download_levels(context, &pool, layout)?
+            .save_ktx2_cubemap(directory.join(Self::IRRADIANCE_FILE))?;
+        self.prefiltered
+            .image
+            .download_levels(context, &pool, layout)?
+            .save_ktx2_cubemap(directory.join(Self::PREFILTERED_FILE))?;
+        self.brdf_lut
+            .image
+            .download_levels(context, &pool, layout)?
+            .save_ktx2(directory.join(Self::BRDF_LUT_FILE))?;
+
+        std::fs::write(key_path, format!("{:016x}", key))?;
+        Ok(())
+    }
+
+    fn transient_pool(context: &Context) -> Result<CommandPool> {
+        CommandPool::new(
+            context.device.clone(),
+            context.graphics_queue(),
+            vk::CommandPoolCreateInfo::builder()
+                .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+                .queue_family_index(context.physical_device.graphics_queue_family_index),
+        )
+    }
+
+    fn record_irradiance(
+        batch: &mut UploadBatch,
+        context: &Context,
+        kernel: &ComputeKernel,
+        environment: &Cubemap,
+        sampler: vk::Sampler,
+        settings: &IblSettings,
+    ) -> Result<Cubemap> {
+        let size = settings.irradiance_size;
+        let mut description = ImageDescription::empty(size, size, Self::CUBEMAP_FORMAT);
+        description.mip_levels = 1;
+        description.array_layers = 6;
+        let irradiance = Cubemap::new_storage(context, &description)?;
+
+        let environment_size = environment.image.extent.width as f32;
+        let highest_lod = (environment.image.mip_levels - 1) as f32;
+        let push_constants = IrradiancePushConstantBlock {
+            sample_delta: settings.irradiance_sample_delta,
+            source_lod: (environment_size / Self::IRRADIANCE_SOURCE_SIZE)
+                .log2()
+                .max(0.0)
+                .min(highest_lod),
+        };
+
+        let face_view = irradiance.image.storage_view(0)?;
+        irradiance.image.transition_levels_to_general(batch)?;
+        kernel.dispatch(
+            batch,
+            &[
+                KernelBinding::Sampled(environment.view.handle, sampler),
+                KernelBinding::Storage(face_view.handle),
+            ],
+            unsafe { byte_slice_from(&push_constants) },
+            [work_group_count(size), work_group_count(size), 6],
+        )?;
+        irradiance.image.transition_general_to_shader_read(batch)?;
+        batch.retain(face_view);
+        Ok(irradiance)
+    }
+
+    fn record_prefiltered(
+        batch: &mut UploadBatch,
+        context: &Context,
+        kernel: &ComputeKernel,
+        environment: &Cubemap,
+        sampler: vk::Sampler,
+        settings: &IblSettings,
+    ) -> Result<Cubemap> {
+        let size = settings.prefiltered_size;
+        let mut description = ImageDescription::empty(size, size, Self::CUBEMAP_FORMAT);
+        description.mip_levels = settings.prefiltered_mip_levels();
+        description.array_layers = 6;
+        let prefiltered = Cubemap::new_storage(context, &description)?;
+
+        prefiltered.image.transition_levels_to_general(batch)?;
+        let highest_level = (description.mip_levels - 1).max(1) as f32;
+        for level in 0..description.mip_levels {
+            let push_constants = PrefilterPushConstantBlock {
+                roughness: level as f32 / highest_level,
+                sample_count: settings.prefiltered_sample_count,
+                environment_size: environment.image.extent.width as f32,
+            };
+            let (width, height) = description.mip_extent(level);
+            let level_view = prefiltered.image.storage_view(level)?;
+            kernel.dispatch(
+                batch,
+                &[
+                    KernelBinding::Sampled(environment.view.handle, sampler),
+                    KernelBinding::Storage(level_view.handle),
+                ],
+                unsafe { byte_slice_from(&push_constants) },
+                [work_group_count(width), work_group_count(height), 6],
+            )?;
+            batch.retain(level_view);
+        }
+        prefiltered.image.transition_general_to_shader_read(batch)?;
+        Ok(prefiltered)
+    }
+
+    fn record_brdf_lut(
+        batch: &mut UploadBatch,
+        context: &Context,
+        kernel: &ComputeKernel,
+        settings: &IblSettings,
+    ) -> Result<Texture> {
+        let size = settings.brdf_lut_size;
+        let mut description = ImageDescription::empty(size, size, Self::BRDF_LUT_FORMAT);
+        description.mip_levels = 1;
+        let brdf_lut = Texture::new_storage(context, &description)?;
+
+        let push_constants = BrdfLutPushConstantBlock {
+            sample_count: settings.brdf_lut_sample_count,
+        };
+        let lut_view = brdf_lut.image.storage_view(0)?;
+        brdf_lut.image.transition_levels_to_general(batch)?;
+        kernel.dispatch(
+            batch,
+            &[KernelBinding::Storage(lut_view.handle)],
+            unsafe { byte_slice_from(&push_constants) },
+            [work_group_count(size), work_group_count(size), 1],
+        )?;
+        brdf_lut.image.transition_general_to_shader_read(batch)?;
+        batch.retain(lut_view);
+        Ok(brdf_lut)
+    }
+
+    fn ensure_storage_supported(context: &Context, format: vk::Format) -> Result<()> {
+        let supported = context
+            .physical_device_format_properties(format)
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::STORAGE_IMAGE);
+        ensure!(
+            supported,
+            "Format {:?} cannot be written by compute shaders, which image based lighting requires!",
+            format
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,394 @@
+use crate::vulkan::core::{Context, Device, GpuBuffer, UploadBatch};
+use anyhow::{bail, ensure, Context as AnyhowContext, Result};
+use ash::{version::DeviceV1_0, vk};
+use std::{
+    convert::TryFrom,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+use vk_mem::Allocator;
+
+/// An index type that geometry can be uploaded with
+pub trait Index: Copy {
+    const INDEX_TYPE: vk::IndexType;
+}
+
+impl Index for u16 {
+    const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT16;
+}
+
+impl Index for u32 {
+    const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT32;
+}
+
+/// The location of a mesh inside a `GeometryArena`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshHandle {
+    pub base_vertex: u32,
+    pub vertex_count: u32,
+    pub first_index: u32,
+    pub index_count: u32,
+    pub index_type: vk::IndexType,
+}
+
+impl MeshHandle {
+    /// Expects the arena to be bound with this mesh's index type
+    pub fn draw(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, instances: u32) {
+        unsafe {
+            if self.index_count == 0 {
+                device.cmd_draw(
+                    command_buffer,
+                    self.vertex_count,
+                    instances,
+                    self.base_vertex,
+                    0,
+                );
+            } else {
+                device.cmd_draw_indexed(
+                    command_buffer,
+                    self.index_count,
+                    instances,
+                    self.first_index,
+                    self.base_vertex as i32,
+                    0,
+                );
+            }
+        }
+    }
+
+    pub fn indirect_command(
+        &self,
+        instance_count: u32,
+        first_instance: u32,
+    ) -> vk::DrawIndexedIndirectCommand {
+        vk::DrawIndexedIndirectCommand {
+            index_count: self.index_count,
+            instance_count,
+            first_index: self.first_index,
+            vertex_offset: self.base_vertex as i32,
+            first_instance,
+        }
+    }
+}
+
+/// Shared vertex and index buffers that many meshes are suballocated from.
+///
+/// Every mesh shares one vertex layout. 16-bit and 32-bit indices live in separate buffers,
+/// so a scene can be drawn with one bind per index type.
+pub struct GeometryArena {
+    vertex_buffer: GpuBuffer,
+    vertex_stride: u32,
+    index_buffer_u16: GpuBuffer,
+    index_buffer_u32: GpuBuffer,
+    ranges: Arc<Mutex<ArenaRanges>>,
+    device: Arc<Device>,
+}
+
+struct ArenaRanges {
+    vertices: RangeAllocator,
+    indices_u16: RangeAllocator,
+    indices_u32: RangeAllocator,
+}
+
+impl ArenaRanges {
+    fn indices(&mut self, index_type: vk::IndexType) -> Result<&mut RangeAllocator> {
+        match index_type {
+            vk::IndexType::UINT16 => Ok(&mut self.indices_u16),
+            vk::IndexType::UINT32 => Ok(&mut self.indices_u32),
+            _ => bail!("Index type {:?} is not supported!", index_type),
+        }
+    }
+
+    fn free(&mut self, mesh: &MeshHandle) -> Result<()> {
+        self.vertices
+            .free(mesh.base_vertex..mesh.base_vertex + mesh.vertex_count);
+        if mesh.index_count > 0 {
+            self.indices(mesh.index_type)?
+                .free(mesh.first_index..mesh.first_index + mesh.index_count);
+        }
+        Ok(())
+    }
+}
+
+impl GeometryArena {
+    pub fn new(
+        device: Arc<Device>,
+        allocator: Arc<Allocator>,
+        vertex_stride: u32,
+        vertex_capacity: u32,
+        index_capacity: u32,
+    ) -> Result<Self> {
+        let index_count = index_capacity as vk::DeviceSize;
+        let vertex_buffer = GpuBuffer::vertex_buffer(
+            device.clone(),
+            allocator.clone(),
+            vertex_stride as vk::DeviceSize * vertex_capacity as vk::DeviceSize,
+        )?;
+        let index_buffer_u16 =
+            GpuBuffer::index_buffer(device.clone(), allocator.clone(), index_count * 2)?;
+        let index_buffer_u32 = GpuBuffer::index_buffer(device.clone(), allocator, index_count * 4)?;
+        let ranges = ArenaRanges {
+            vertices: RangeAllocator::new(vertex_capacity),
+            indices_u16: RangeAllocator::new(index_capacity),
+            indices_u32: RangeAllocator::new(index_capacity),
+        };
+        Ok(Self {
+            vertex_buffer,
+            vertex_stride,
+            index_buffer_u16,
+            index_buffer_u32,
+            ranges: Arc::new(Mutex::new(ranges)),
+            device,
+        })
+    }
+
+    /// Uploads a mesh and waits for the upload to finish
+    pub fn upload_mesh<V: Copy, I: Index>(
+        &self,
+        context: &Context,
+        vertices: &[V],
+        indices: &[I],
+    ) -> Result<MeshHandle> {
+        let mut batch = UploadBatch::new(context)?;
+        let mesh = self.record_mesh(&mut batch, vertices, indices)?;
+        batch.submit()?.wait()?;
+        Ok(mesh)
+    }
+
+    /// Reserves space for a mesh and records its upload into the batch.
+    /// The batch must not use the transfer queue, since the arena buffers are exclusively owned
+    /// by the graphics queue while other meshes in them are being drawn.
+    pub fn record_mesh<V: Copy, I: Index>(
+        &self,
+        batch: &mut UploadBatch,
+        vertices: &[V],
+        indices: &[I],
+    ) -> Result<MeshHandle> {
+        ensure!(
+            !batch.uses_transfer_queue(),
+            "Meshes can not be uploaded to the geometry arena on the transfer queue!"
+        );
+        ensure!(
+            std::mem::size_of::<V>() as u32 == self.vertex_stride,
+            "Vertex size {} does not match the arena's vertex stride of {}!",
+            std::mem::size_of::<V>(),
+            self.vertex_stride
+        );
+
+        let vertex_count =
+            u32::try_from(vertices.len()).context("Mesh has too many vertices for the arena!")?;
+        let index_count =
+            u32::try_from(indices.len()).context("Mesh has too many indices for the arena!")?;
+        let mesh = self.allocate(vertex_count, index_count, I::INDEX_TYPE)?;
+        let uploaded = self.upload(batch, &mesh, vertices, indices);
+        if uploaded.is_err() {
+            self.lock_ranges()?.free(&mesh)?;
+        }
+        uploaded.map(|_| mesh)
+    }
+
+    /// Frees the mesh's ranges once every frame that may still draw it has finished
+    pub fn remove_mesh(&self, mesh: MeshHandle) {
+        let ranges = self.ranges.clone();
+        self.device.defer_destruction(move |_| {
+            let freed = ranges
+                .lock()
+                .ok()
+                .context("Failed to lock the geometry arena!")
+                .and_then(|mut ranges| ranges.free(&mesh));
+            if let Err(error) = freed {
+                log::error!("Failed to free mesh: {}", error);
+            }
+        });
+    }
+
+    /// Binds the shared vertex buffer and the index buffer for meshes with the given index type
+    pub fn bind(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        index_type: vk::IndexType,
+    ) -> Result<()> {
+        let index_buffer = match index_type {
+            vk::IndexType::UINT16 => &self.index_buffer_u16,
+            vk::IndexType::UINT32 => &self.index_buffer_u32,
+            _ => bail!("Index type {:?} is not supported!", index_type),
+        };
+        unsafe {
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffer.handle()], &[0]);
+            device.cmd_bind_index_buffer(command_buffer, index_buffer.handle(), 0, index_type);
+        }
+        Ok(())
+    }
+
+    fn allocate(
+        &self,
+        vertex_count: u32,
+        index_count: u32,
+        index_type: vk::IndexType,
+    ) -> Result<MeshHandle> {
+        let mut ranges = self.lock_ranges()?;
+        let vertices = ranges
+            .vertices
+            .allocate(vertex_count)
+            .context("Geometry arena is out of vertex space!")?;
+        let indices = if index_count == 0 {
+            0..0
+        } else {
+            match ranges.indices(index_type)?.allocate(index_count) {
+                Some(indices) => indices,
+                None => {
+                    ranges.vertices.free(vertices);
+                    bail!("Geometry arena is out of index space!");
+                }
+            }
+        };
+        Ok(MeshHandle {
+            base_vertex: vertices.start,
+            vertex_count,
+            first_index: indices.start,
+            index_count,
+            index_type,
+        })
+    }
+
+    fn upload<V: Copy, I: Index>(
+        &self,
+        batch: &mut UploadBatch,
+        mesh: &MeshHandle,
+        vertices: &[V],
+        indices: &[I],
+    ) -> Result<()> {
+        let vertex_offset =
+            mesh.base_vertex as vk::DeviceSize * self.vertex_stride as vk::DeviceSize;
+        self.vertex_buffer
+            .record_upload(batch, vertices, vertex_offset as _)?;
+        if indices.is_empty() {
+            return Ok(());
+        }
+        let index_offset =
+            mesh.first_index as vk::DeviceSize * std::mem::size_of::<I>() as vk::DeviceSize;
+        let index_buffer = match I::INDEX_TYPE {
+            vk::IndexType::UINT16 => &self.index_buffer_u16,
+            _ => &self.index_buffer_u32,
+        };
+        index_buffer.record_upload(batch, indices, index_offset as _)
+    }
+
+    fn lock_ranges(&self) -> Result<std::sync::MutexGuard<ArenaRanges>> {
+        self.ranges
+            .lock()
+            .ok()
+            .context("Failed to lock the geometry arena!")
+    }
+}
+
+/// First fit allocation of element ranges, merging neighbors when they are freed
+struct RangeAllocator {
+    free: Vec<Range<u32>>,
+}
+
+impl RangeAllocator {
+    fn new(capacity: u32) -> Self {
+        Self {
+            free: vec![0..capacity],
+        }
+    }
+
+    fn allocate(&mut self, count: u32) -> Option<Range<u32>> {
+        if count == 0 {
+            return Some(0..0);
+        }
+        let index = self
+            .free
+            .iter()
+            .position(|range| range.end - range.start >= count)?;
+        let range = &mut self.free[index];
+        let allocated = range.start..range.start + count;
+        range.start += count;
+        if range.start == range.end {
+            self.free.remove(index);
+        }
+        Some(allocated)
+    }
+
+    fn free(&mut self, range: Range<u32>) {
+        if range.start == range.end {
+            return;
+        }
+        let index = self
+            .free
+            .iter()
+            .position(|free| free.start > range.start)
+            .unwrap_or(self.free.len());
+        self.free.insert(index, range);
+
+        // Merge with the following range, then the preceding one
+        if index + 1 < self.free.len() && self.free[index].end == self.free[index + 1].start {
+            self.free[index].end = self.free.remove(index + 1).end;
+        }
+        if index > 0 && self.free[index - 1].end == self.free[index].start {
+            self.free[index - 1].end = self.free.remove(index).end;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RangeAllocator;
+
+    #[test]
+    fn allocates_first_fit_until_exhausted() {
+        let mut allocator = RangeAllocator::new(10);
+        assert_eq!(allocator.allocate(4), Some(0..4));
+        assert_eq!(allocator.allocate(6), Some(4..10));
+        assert_eq!(allocator.allocate(1), None);
+        assert_eq!(allocator.allocate(0), Some(0..0));
+    }
+
+    #[test]
+    fn reuses_freed_ranges() {
+        let mut allocator = RangeAllocator::new(10);
+        let first = allocator.allocate(4).unwrap();
+        allocator.allocate(6).unwrap();
+        allocator.free(first);
+        assert_eq!(allocator.allocate(5), None);
+        assert_eq!(allocator.allocate(3), Some(0..3));
+        assert_eq!(allocator.allocate(1), Some(3..4));
+    }
+
+    #[test]
+    fn merges_with_both_neighbors() {
+        let mut allocator = RangeAllocator::new(9);
+        let first = allocator.allocate(3).unwrap();
+        let second = allocator.allocate(3).unwrap();
+        let third = allocator.allocate(3).unwrap();
+
+        allocator.free(first);
+        allocator.free(third);
+        assert_eq!(allocator.free, vec![0..3, 6..9]);
+
+        // The middle range joins the ranges before and after it
+        allocator.free(second);
+        assert_eq!(allocator.free, vec![0..9]);
+        assert_eq!(allocator.allocate(9), Some(0..9));
+    }
+
+    #[test]
+    fn merges_with_the_following_range() {
+        let mut allocator = RangeAllocator::new(6);
+        let first = allocator.allocate(3).unwrap();
+        allocator.free(first);
+        assert_eq!(allocator.free, vec![0..6]);
+    }
+
+    #[test]
+    fn merges_with_the_preceding_range() {
+        let mut allocator = RangeAllocator::new(6);
+        allocator.allocate(3).unwrap();
+        let second = allocator.allocate(3).unwrap();
+        allocator.free(0..3);
+        allocator.free(second);
+        assert_eq!(allocator.free, vec![0..6]);
+    }
+}
@@ -1,12 +1,17 @@
 use crate::vulkan::core::{
-    BlitImageBuilder, BufferToImageCopyBuilder, CommandPool, Context, CpuToGpuBuffer, Device,
-    PipelineBarrierBuilder,
+    storage_format, work_group_count, AsyncUpload, BlitImageBuilder, BufferToImageCopyBuilder,
+    CommandPool, ComputeKernel, Context, Device, ImageToBufferCopyBuilder, KernelBinding,
+    MemoryCategory, MipmapFilter, MipmapGenerator, MipmapMethod, PipelineBarrier,
+    PipelineBarrierBuilder, ReadbackBuffer, Sampler, SamplerDesc, StagedRegion, UploadBatch,
 };
-use anyhow::{anyhow, bail, Context as AnyhowContext, Result};
+use anyhow::{anyhow, bail, ensure, Context as AnyhowContext, Result};
 use ash::{version::DeviceV1_0, vk};
 use derive_builder::Builder;
-use image::{hdr::HdrDecoder, DynamicImage, ImageBuffer, Pixel, RgbImage};
+use exr::prelude::write_rgba_file;
+use image::{hdr::HdrDecoder, DynamicImage, ImageBuffer, Pixel, RgbImage, RgbaImage};
+use log::warn;
 use std::{
+    convert::TryFrom,
     io::BufReader,
     path::{Path, PathBuf},
     sync::Arc,
@@ -29,12 +34,43 @@ pub struct ImageLayoutTransition {
     pub dst_stage_mask: vk::PipelineStageFlags,
 }
 
+/// What the pixels of an image hold, which decides whether
+/// formats with an sRGB variant are decoded to linear when sampled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    /// Colors such as albedo and emission, stored with sRGB encoding where the format allows it
+    Color,
+    /// Normals, roughness, masks and lookup tables, sampled exactly as they are stored
+    Data,
+}
+
+impl ColorSpace {
+    /// The color space that a format implies on its own
+    pub fn of_format(format: vk::Format) -> Self {
+        if linear_format(format) != format {
+            ColorSpace::Color
+        } else {
+            ColorSpace::Data
+        }
+    }
+}
+
 pub struct ImageDescription {
     pub format: vk::Format,
     pub width: u32,
     pub height: u32,
+    /// Slices of a volume texture, or 1 for everything else
+    pub depth: u32,
     pub pixels: Vec<u8>,
     pub mip_levels: u32,
+    /// Array layers or cube faces, stored back to back within each mip level
+    pub array_layers: u32,
+    /// Byte offsets of precomputed mip levels within the pixels.
+    /// Empty when only the base level is present and the rest are generated on upload.
+    pub mip_offsets: Vec<usize>,
+    pub mip_filter: MipmapFilter,
+    /// Kept so that materials can tell color textures from data textures
+    pub color_space: ColorSpace,
 }
 
 impl ImageDescription {
@@ -43,20 +79,41 @@ impl ImageDescription {
             format,
             width,
             height,
+            depth: 1,
             pixels: Vec::new(),
             mip_levels: Self::calculate_mip_levels(width, height),
+            array_layers: 1,
+            mip_offsets: Vec::new(),
+            mip_filter: MipmapFilter::default(),
+            color_space: ColorSpace::of_format(format),
         }
     }
 
+    /// Loads KTX2 and DDS files with their mips and layers intact,
+    /// and anything else through the `image` crate.
+    /// The color space picks between the sRGB and UNORM variants of formats that do not say which they are,
+    /// which are those of legacy DDS files and everything the `image` crate loads.
+    /// The formats of KTX2 files and DDS files with a DX10 header are kept.
     #[allow(dead_code)]
-    pub fn from_file<P>(path: P) -> Result<Self>
+    pub fn from_file<P>(path: P, color_space: ColorSpace) -> Result<Self>
     where
         P: AsRef<Path> + Into<PathBuf>,
     {
-        let path_display = path.as_ref().display().to_string();
-        let image =
-            image::open(path).map_err(|error| anyhow!("{}\npath: {}", error, path_display))?;
-        Self::from_image(&image)
+        let extension = path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("ktx2") => Self::from_ktx2(path),
+            Some("dds") => Self::from_dds_with_color_space(path, color_space),
+            _ => {
+                let path_display = path.as_ref().display().to_string();
+                let image = image::open(path)
+                    .map_err(|error| anyhow!("{}\npath: {}", error, path_display))?;
+                Self::from_image(&image, color_space)
+            }
+        }
     }
 
     #[allow(dead_code)]
@@ -83,13 +140,20 @@ impl ImageDescription {
             format,
             width,
             height,
+            depth: 1,
             pixels,
             mip_levels,
+            array_layers: 1,
+            mip_offsets: Vec::new(),
+            mip_filter: MipmapFilter::default(),
+            color_space: ColorSpace::Color,
         })
     }
 
+    /// Eight bit images are given sRGB formats when they hold color.
+    /// Sixteen bit formats have no sRGB variant and are always sampled as stored.
     #[allow(dead_code)]
-    pub fn from_image(image: &DynamicImage) -> Result<Self> {
+    pub fn from_image(image: &DynamicImage, color_space: ColorSpace) -> Result<Self> {
         let (format, (width, height)) = match image {
             DynamicImage::ImageRgb8(buffer) => (vk::Format::R8G8B8_UNORM, buffer.dimensions()),
             DynamicImage::ImageRgba8(buffer) => (vk::Format::R8G8B8A8_UNORM, buffer.dimensions()),
@@ -106,29 +170,331 @@ impl ImageDescription {
             format,
             width,
             height,
+            depth: 1,
             pixels: image.to_bytes(),
             mip_levels: Self::calculate_mip_levels(width, height),
+            array_layers: 1,
+            mip_offsets: Vec::new(),
+            mip_filter: MipmapFilter::default(),
+            color_space,
         };
         description.convert_24bit_formats()?;
+        description.set_color_space(color_space);
+        Ok(description)
+    }
+
+    /// Records what the pixels hold, switching to the sRGB or UNORM variant of the format if it has one.
+    /// The pixels are stored the same way in both, so they are left as they are.
+    pub fn set_color_space(&mut self, color_space: ColorSpace) {
+        self.format = match color_space {
+            ColorSpace::Color => srgb_format(self.format),
+            ColorSpace::Data => linear_format(self.format),
+        };
+        self.color_space = color_space;
+    }
+
+    /// Stacks images of the same size and format into the layers of one image.
+    /// Precomputed mips are kept when every image has the same number of them,
+    /// otherwise only the base levels are kept and the rest are generated on upload.
+    pub fn from_layers(layers: &[ImageDescription]) -> Result<Self> {
+        let first = layers
+            .first()
+            .context("An image needs at least one layer!")?;
+        for layer in layers.iter() {
+            ensure!(
+                layer.width == first.width
+                    && layer.height == first.height
+                    && layer.format == first.format,
+                "Layers must share a size and format, found {}x{} {:?} and {}x{} {:?}!",
+                first.width,
+                first.height,
+                first.format,
+                layer.width,
+                layer.height,
+                layer.format
+            );
+            ensure!(
+                layer.array_layers == 1 && !layer.is_volume(),
+                "Only 2D images can be stacked into layers!"
+            );
+        }
+        let keep_mips = layers
+            .iter()
+            .all(|layer| layer.has_precomputed_mips() && layer.mip_levels == first.mip_levels);
+
+        let mut description = Self::empty(first.width, first.height, first.format);
+        description.array_layers = layers.len() as _;
+        description.mip_filter = first.mip_filter;
+        description.color_space = first.color_space;
+        // Only the levels that are copied are laid out here
+        description.mip_levels = if keep_mips { first.mip_levels } else { 1 };
+        let (level_offsets, size) = description.packed_level_offsets()?;
+
+        // Each level holds every layer back to back
+        let mut pixels = vec![0; size];
+        for (level, level_offset) in level_offsets.iter().enumerate() {
+            let layer_size = first.level_size(level as _)?;
+            for (index, layer) in layers.iter().enumerate() {
+                let source = if keep_mips {
+                    layer.mip_offsets[level]
+                } else {
+                    0
+                };
+                let destination = level_offset + index * layer_size;
+                pixels[destination..destination + layer_size].copy_from_slice(
+                    layer
+                        .pixels
+                        .get(source..source + layer_size)
+                        .context("Layer is smaller than its size and format describe!")?,
+                );
+            }
+        }
+        description.pixels = pixels;
+        if keep_mips {
+            description.mip_offsets = level_offsets;
+        } else {
+            description.mip_levels = Self::calculate_mip_levels(first.width, first.height);
+        }
+        Ok(description)
+    }
+
+    /// A volume from tightly packed slices, stored front to back. Volumes have no mipmaps.
+    pub fn volume(
+        width: u32,
+        height: u32,
+        depth: u32,
+        format: vk::Format,
+        pixels: Vec<u8>,
+    ) -> Result<Self> {
+        let mut description = Self::empty(width, height, format);
+        description.depth = depth.max(1);
+        description.mip_levels = 1;
+        let size = description.level_size(0)?;
+        ensure!(
+            pixels.len() == size,
+            "A {}x{}x{} volume of {:?} needs {} bytes, but {} were provided!",
+            width,
+            height,
+            depth,
+            format,
+            size,
+            pixels.len()
+        );
+        description.pixels = pixels;
+        Ok(description)
+    }
+
+    /// Stacks 2D images of the same size and format into the slices of a volume
+    pub fn from_slices(slices: &[ImageDescription]) -> Result<Self> {
+        let mut description = Self::from_layers(slices)?;
+        let base_size = description.level_size(0)?;
+        description.pixels.truncate(base_size);
+        description.depth = description.array_layers;
+        description.array_layers = 1;
+        description.mip_levels = 1;
+        description.mip_offsets.clear();
         Ok(description)
     }
 
+    /// Whether `save` can write images of the format
+    pub fn is_saveable(format: vk::Format) -> bool {
+        matches!(
+            format,
+            vk::Format::R8G8B8A8_UNORM
+                | vk::Format::R8G8B8A8_SRGB
+                | vk::Format::B8G8R8A8_UNORM
+                | vk::Format::B8G8R8A8_SRGB
+                | vk::Format::A2B10G10R10_UNORM_PACK32
+                | vk::Format::A2R10G10B10_UNORM_PACK32
+                | vk::Format::R16G16B16A16_SFLOAT
+                | vk::Format::R32G32B32A32_SFLOAT
+        )
+    }
+
+    /// Writes the pixels to disk. Eight and ten bit formats are written as PNG and float formats as EXR.
+    /// The extension of the path is replaced to match, and the path that was written is returned.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        match self.format {
+            vk::Format::R16G16B16A16_SFLOAT | vk::Format::R32G32B32A32_SFLOAT => {
+                let path = path.as_ref().with_extension("exr");
+                let pixels = self.rgba32f_pixels()?;
+                let width = self.width as usize;
+                write_rgba_file(&path, width, self.height as _, |x, y| {
+                    let [red, green, blue, alpha] = pixels[y * width + x];
+                    (red, green, blue, alpha)
+                })
+                .map_err(|error| anyhow!("Failed to write {}: {}", path.display(), error))?;
+                Ok(path)
+            }
+            _ => {
+                let path = path.as_ref().with_extension("png");
+                let image: RgbaImage =
+                    ImageBuffer::from_raw(self.width, self.height, self.rgba8_pixels()?)
+                        .context("Failed to create an image from the raw pixels!")?;
+                image.save(&path)?;
+                Ok(path)
+            }
+        }
+    }
+
+    fn rgba8_pixels(&self) -> Result<Vec<u8>> {
+        let pixels = match self.format {
+            vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => self.pixels.clone(),
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => self
+                .pixels
+                .chunks_exact(4)
+                .flat_map(|pixel| vec![pixel[2], pixel[1], pixel[0], pixel[3]])
+                .collect(),
+            vk::Format::A2B10G10R10_UNORM_PACK32 | vk::Format::A2R10G10B10_UNORM_PACK32 => self
+                .unpack_10bit_pixels()
+                .into_iter()
+                .flat_map(|pixel| {
+                    let channel = |index: usize| (pixel[index] * 255.0).round() as u8;
+                    vec![channel(0), channel(1), channel(2), channel(3)]
+                })
+                .collect(),
+            _ => bail!(
+                "Saving images with format {:?} as PNG is not supported!",
+                self.format
+            ),
+        };
+        Ok(pixels)
+    }
+
+    /// The pixels as normalized or float RGBA values, for formats with at least ten bits per channel
+    pub fn rgba32f_pixels(&self) -> Result<Vec<[f32; 4]>> {
+        let pixels = match self.format {
+            vk::Format::R32G32B32A32_SFLOAT => self
+                .pixels
+                .chunks_exact(16)
+                .map(|pixel| {
+                    let channel = |index: usize| {
+                        let bytes = &pixel[index * 4..index * 4 + 4];
+                        f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                    };
+                    [channel(0), channel(1), channel(2), channel(3)]
+                })
+                .collect(),
+            vk::Format::R16G16B16A16_SFLOAT => self
+                .pixels
+                .chunks_exact(8)
+                .map(|pixel| {
+                    let channel = |index: usize| {
+                        half_to_f32(u16::from_le_bytes([pixel[index * 2], pixel[index * 2 + 1]]))
+                    };
+                    [channel(0), channel(1), channel(2), channel(3)]
+                })
+                .collect(),
+            vk::Format::A2B10G10R10_UNORM_PACK32 | vk::Format::A2R10G10B10_UNORM_PACK32 => {
+                self.unpack_10bit_pixels()
+            }
+            _ => bail!(
+                "Reading images with format {:?} as float pixels is not supported!",
+                self.format
+            ),
+        };
+        Ok(pixels)
+    }
+
+    /// Unpacks A2B10G10R10 and A2R10G10B10 pixels into normalized RGBA
+    fn unpack_10bit_pixels(&self) -> Vec<[f32; 4]> {
+        // A2B10G10R10 stores red in the lowest bits, A2R10G10B10 stores blue there
+        let (red_shift, blue_shift) = match self.format {
+            vk::Format::A2R10G10B10_UNORM_PACK32 => (20, 0),
+            _ => (0, 20),
+        };
+        self.pixels
+            .chunks_exact(4)
+            .map(|pixel| {
+                let value = u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                let channel = |shift: u32| (value >> shift & 0x3ff) as f32 / 1023.0;
+                [
+                    channel(red_shift),
+                    channel(10),
+                    channel(blue_shift),
+                    (value >> 30) as f32 / 3.0,
+                ]
+            })
+            .collect()
+    }
+
     pub fn calculate_mip_levels(width: u32, height: u32) -> u32 {
         ((width.min(height) as f32).log2().floor() + 1.0) as u32
     }
 
+    pub fn mip_extent(&self, level: u32) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    pub fn mip_depth(&self, level: u32) -> u32 {
+        (self.depth >> level).max(1)
+    }
+
+    pub fn is_volume(&self) -> bool {
+        self.depth > 1
+    }
+
+    pub fn has_precomputed_mips(&self) -> bool {
+        !self.mip_offsets.is_empty()
+    }
+
+    /// Picks how missing mip levels are generated, preferring blits for the linear filter
+    /// and falling back to the compute shader for formats that cannot be blitted
+    pub fn mipmap_method(&self, context: &Context) -> Result<MipmapMethod> {
+        if self.has_precomputed_mips() || self.mip_levels <= 1 {
+            return Ok(MipmapMethod::Precomputed);
+        }
+        ensure!(
+            !self.is_volume(),
+            "Mipmaps cannot be generated for volume textures, only precomputed levels can be uploaded!"
+        );
+        let blit_supported = context
+            .ensure_linear_blitting_supported(self.format)
+            .is_ok();
+        let compute_supported = MipmapGenerator::supports_format(context, self.format);
+        match (self.mip_filter, blit_supported, compute_supported) {
+            (MipmapFilter::Linear, true, _) => Ok(MipmapMethod::Blit),
+            (_, _, true) => Ok(MipmapMethod::Compute),
+            (_, true, false) => {
+                warn!(
+                    "Format {:?} cannot be written by compute shaders, generating mipmaps with linear blits instead",
+                    self.format
+                );
+                Ok(MipmapMethod::Blit)
+            }
+            _ => bail!(
+                "Mipmaps cannot be generated for format {:?}, which supports neither linear blitting nor storage writes",
+                self.format
+            ),
+        }
+    }
+
     fn convert_24bit_formats(&mut self) -> Result<()> {
         // 24-bit formats are unsupported, so they
         // need to have an alpha channel added to make them 32-bit
         let format = match self.format {
             vk::Format::R8G8B8_UNORM => vk::Format::R8G8B8A8_UNORM,
+            vk::Format::R8G8B8_SRGB => vk::Format::R8G8B8A8_SRGB,
             vk::Format::B8G8R8_UNORM => vk::Format::B8G8R8A8_UNORM,
+            vk::Format::B8G8R8_SRGB => vk::Format::B8G8R8A8_SRGB,
             _ => return Ok(()),
         };
         self.format = format;
         self.attach_alpha_channel()
     }
 
+    fn convert_bgra_formats(&mut self) {
+        let format = match self.format {
+            vk::Format::B8G8R8A8_UNORM => vk::Format::R8G8B8A8_UNORM,
+            vk::Format::B8G8R8A8_SRGB => vk::Format::R8G8B8A8_SRGB,
+            _ => return,
+        };
+        self.format = format;
+        self.pixels
+            .chunks_exact_mut(4)
+            .for_each(|pixel| pixel.swap(0, 2));
+    }
+
     fn attach_alpha_channel(&mut self) -> Result<()> {
         let image_buffer: RgbImage =
             ImageBuffer::from_raw(self.width, self.height, self.pixels.to_vec())
@@ -142,38 +508,100 @@ impl ImageDescription {
         Ok(())
     }
 
-    pub fn as_image(&self, allocator: Arc<Allocator>) -> Result<AllocatedImage> {
-        self.create_image(allocator, vk::ImageCreateFlags::empty(), 1)
+    pub fn as_image(&self, context: &Context) -> Result<AllocatedImage> {
+        let image_type = if self.is_volume() {
+            vk::ImageType::TYPE_3D
+        } else {
+            vk::ImageType::TYPE_2D
+        };
+        self.create_image(
+            context,
+            image_type,
+            vk::ImageCreateFlags::empty(),
+            self.array_layers,
+            vk::ImageUsageFlags::empty(),
+        )
     }
 
-    pub fn as_cubemap(&self, allocator: Arc<Allocator>) -> Result<AllocatedImage> {
-        self.create_image(allocator, vk::ImageCreateFlags::CUBE_COMPATIBLE, 6)
+    pub fn as_cubemap(&self, context: &Context) -> Result<AllocatedImage> {
+        self.create_image(
+            context,
+            vk::ImageType::TYPE_2D,
+            vk::ImageCreateFlags::CUBE_COMPATIBLE,
+            6,
+            vk::ImageUsageFlags::empty(),
+        )
+    }
+
+    /// A 3D image, even when the volume is a single slice deep
+    pub fn as_volume(&self, context: &Context) -> Result<AllocatedImage> {
+        self.create_image(
+            context,
+            vk::ImageType::TYPE_3D,
+            vk::ImageCreateFlags::empty(),
+            1,
+            vk::ImageUsageFlags::empty(),
+        )
+    }
+
+    /// An image that compute shaders can write to
+    pub fn as_storage_image(&self, context: &Context) -> Result<AllocatedImage> {
+        self.create_image(
+            context,
+            vk::ImageType::TYPE_2D,
+            vk::ImageCreateFlags::empty(),
+            self.array_layers,
+            vk::ImageUsageFlags::STORAGE,
+        )
+    }
+
+    /// A cubemap that compute shaders can write to, face by face as array layers
+    pub fn as_storage_cubemap(&self, context: &Context) -> Result<AllocatedImage> {
+        self.create_image(
+            context,
+            vk::ImageType::TYPE_2D,
+            vk::ImageCreateFlags::CUBE_COMPATIBLE,
+            6,
+            vk::ImageUsageFlags::STORAGE,
+        )
     }
 
     fn create_image(
         &self,
-        allocator: Arc<Allocator>,
-        flags: vk::ImageCreateFlags,
+        context: &Context,
+        image_type: vk::ImageType,
+        mut flags: vk::ImageCreateFlags,
         layers: u32,
+        extra_usage: vk::ImageUsageFlags,
     ) -> Result<AllocatedImage> {
+        let mut usage = vk::ImageUsageFlags::TRANSFER_SRC
+            | vk::ImageUsageFlags::TRANSFER_DST
+            | vk::ImageUsageFlags::SAMPLED
+            | extra_usage;
+
+        // Compute mipmap generation writes sRGB images through a UNORM view
+        if !self.pixels.is_empty() && self.mipmap_method(context)? == MipmapMethod::Compute {
+            usage |= vk::ImageUsageFlags::STORAGE;
+            if storage_format(self.format) != self.format {
+                flags |=
+                    vk::ImageCreateFlags::MUTABLE_FORMAT | vk::ImageCreateFlags::EXTENDED_USAGE;
+            }
+        }
+
         let extent = vk::Extent3D::builder()
             .width(self.width)
             .height(self.height)
-            .depth(1);
+            .depth(self.depth);
 
         let create_info = vk::ImageCreateInfo::builder()
-            .image_type(vk::ImageType::TYPE_2D)
+            .image_type(image_type)
             .extent(extent.build())
             .mip_levels(self.mip_levels)
             .array_layers(layers)
             .format(self.format)
             .tiling(vk::ImageTiling::OPTIMAL)
             .initial_layout(vk::ImageLayout::UNDEFINED)
-            .usage(
-                vk::ImageUsageFlags::TRANSFER_SRC
-                    | vk::ImageUsageFlags::TRANSFER_DST
-                    | vk::ImageUsageFlags::SAMPLED,
-            )
+            .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE)
             .samples(vk::SampleCountFlags::TYPE_1)
             .flags(flags);
@@ -183,15 +611,261 @@ impl ImageDescription {
             ..Default::default()
         };
 
-        AllocatedImage::new(allocator, &allocation_create_info, &create_info)
+        let mut image = AllocatedImage::new(
+            context.device.clone(),
+            context.allocator.clone(),
+            &allocation_create_info,
+            &create_info,
+        )?;
+        image.set_memory_category(MemoryCategory::Textures);
+        Ok(image)
+    }
+}
+
+fn half_to_f32(bits: u16) -> f32 {
+    let exponent = (bits >> 10 & 0x1f) as u32;
+    let mantissa = (bits & 0x3ff) as u32;
+    let magnitude = match exponent {
+        0 => mantissa as f32 * 2f32.powi(-24),
+        0x1f if mantissa == 0 => std::f32::INFINITY,
+        0x1f => std::f32::NAN,
+        _ => f32::from_bits((exponent + 112) << 23 | mantissa << 13),
+    };
+    if bits & 0x8000 != 0 {
+        -magnitude
+    } else {
+        magnitude
     }
 }
 
+/// Formats that have both a UNORM and an sRGB variant, as (UNORM, sRGB) pairs
+const SRGB_FORMAT_PAIRS: &[(vk::Format, vk::Format)] = &[
+    (vk::Format::R8_UNORM, vk::Format::R8_SRGB),
+    (vk::Format::R8G8_UNORM, vk::Format::R8G8_SRGB),
+    (vk::Format::R8G8B8_UNORM, vk::Format::R8G8B8_SRGB),
+    (vk::Format::B8G8R8_UNORM, vk::Format::B8G8R8_SRGB),
+    (vk::Format::R8G8B8A8_UNORM, vk::Format::R8G8B8A8_SRGB),
+    (vk::Format::B8G8R8A8_UNORM, vk::Format::B8G8R8A8_SRGB),
+    (
+        vk::Format::A8B8G8R8_UNORM_PACK32,
+        vk::Format::A8B8G8R8_SRGB_PACK32,
+    ),
+    (
+        vk::Format::BC1_RGB_UNORM_BLOCK,
+        vk::Format::BC1_RGB_SRGB_BLOCK,
+    ),
+    (
+        vk::Format::BC1_RGBA_UNORM_BLOCK,
+        vk::Format::BC1_RGBA_SRGB_BLOCK,
+    ),
+    (vk::Format::BC2_UNORM_BLOCK, vk::Format::BC2_SRGB_BLOCK),
+    (vk::Format::BC3_UNORM_BLOCK, vk::Format::BC3_SRGB_BLOCK),
+    (vk::Format::BC7_UNORM_BLOCK, vk::Format::BC7_SRGB_BLOCK),
+    (
+        vk::Format::ETC2_R8G8B8_UNORM_BLOCK,
+        vk::Format::ETC2_R8G8B8_SRGB_BLOCK,
+    ),
+    (
+        vk::Format::ETC2_R8G8B8A1_UNORM_BLOCK,
+        vk::Format::ETC2_R8G8B8A1_SRGB_BLOCK,
+    ),
+    (
+        vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK,
+        vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK,
+    ),
+    (
+        vk::Format::ASTC_4X4_UNORM_BLOCK,
+        vk::Format::ASTC_4X4_SRGB_BLOCK,
+    ),
+    (
+        vk::Format::ASTC_5X4_UNORM_BLOCK,
+        vk::Format::ASTC_5X4_SRGB_BLOCK,
+    ),
+    (
+        vk::Format::ASTC_5X5_UNORM_BLOCK,
+        vk::Format::ASTC_5X5_SRGB_BLOCK,
+    ),
+    (
+        vk::Format::ASTC_6X5_UNORM_BLOCK,
+        vk::Format::ASTC_6X5_SRGB_BLOCK,
+    ),
+    (
+        vk::Format::ASTC_6X6_UNORM_BLOCK,
+        vk::Format::ASTC_6X6_SRGB_BLOCK,
+    ),
+    (
+        vk::Format::ASTC_8X5_UNORM_BLOCK,
+        vk::Format::ASTC_8X5_SRGB_BLOCK,
+    ),
+    (
+        vk::Format::ASTC_8X6_UNORM_BLOCK,
+        vk::Format::ASTC_8X6_SRGB_BLOCK,
+    ),
+    (
+        vk::Format::ASTC_8X8_UNORM_BLOCK,
+        vk::Format::ASTC_8X8_SRGB_BLOCK,
+    ),
+    (
+        vk::Format::ASTC_10X5_UNORM_BLOCK,
+        vk::Format::ASTC_10X5_SRGB_BLOCK,
+    ),
+    (
+        vk::Format::ASTC_10X6_UNORM_BLOCK,
+        vk::Format::ASTC_10X6_SRGB_BLOCK,
+    ),
+    (
+        vk::Format::ASTC_10X8_UNORM_BLOCK,
+        vk::Format::ASTC_10X8_SRGB_BLOCK,
+    ),
+    (
+        vk::Format::ASTC_10X10_UNORM_BLOCK,
+        vk::Format::ASTC_10X10_SRGB_BLOCK,
+    ),
+    (
+        vk::Format::ASTC_12X10_UNORM_BLOCK,
+        vk::Format::ASTC_12X10_SRGB_BLOCK,
+    ),
+    (
+        vk::Format::ASTC_12X12_UNORM_BLOCK,
+        vk::Format::ASTC_12X12_SRGB_BLOCK,
+    ),
+];
+
+/// The sRGB variant of a format, or the format itself if it has none
+pub fn srgb_format(format: vk::Format) -> vk::Format {
+    SRGB_FORMAT_PAIRS
+        .iter()
+        .find(|(unorm, _)| *unorm == format)
+        .map_or(format, |(_, srgb)| *srgb)
+}
+
+/// The UNORM variant of an sRGB format, or the format itself if it is not sRGB
+pub fn linear_format(format: vk::Format) -> vk::Format {
+    SRGB_FORMAT_PAIRS
+        .iter()
+        .find(|(_, srgb)| *srgb == format)
+        .map_or(format, |(unorm, _)| *unorm)
+}
+
+/// The width, height and size in bytes of a texel block.
+/// Uncompressed formats have blocks of a single texel.
+pub fn format_block_size(format: vk::Format) -> Result<(u32, u32, u32)> {
+    let block = match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC4_SNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8_SRGB_BLOCK
+        | vk::Format::ETC2_R8G8B8A1_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8A1_SRGB_BLOCK
+        | vk::Format::EAC_R11_UNORM_BLOCK
+        | vk::Format::EAC_R11_SNORM_BLOCK => (4, 4, 8),
+        vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC5_SNORM_BLOCK
+        | vk::Format::BC6H_UFLOAT_BLOCK
+        | vk::Format::BC6H_SFLOAT_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK
+        | vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK
+        | vk::Format::EAC_R11G11_UNORM_BLOCK
+        | vk::Format::EAC_R11G11_SNORM_BLOCK
+        | vk::Format::ASTC_4X4_UNORM_BLOCK
+        | vk::Format::ASTC_4X4_SRGB_BLOCK => (4, 4, 16),
+        vk::Format::ASTC_5X4_UNORM_BLOCK | vk::Format::ASTC_5X4_SRGB_BLOCK => (5, 4, 16),
+        vk::Format::ASTC_5X5_UNORM_BLOCK | vk::Format::ASTC_5X5_SRGB_BLOCK => (5, 5, 16),
+        vk::Format::ASTC_6X5_UNORM_BLOCK | vk::Format::ASTC_6X5_SRGB_BLOCK => (6, 5, 16),
+        vk::Format::ASTC_6X6_UNORM_BLOCK | vk::Format::ASTC_6X6_SRGB_BLOCK => (6, 6, 16),
+        vk::Format::ASTC_8X5_UNORM_BLOCK | vk::Format::ASTC_8X5_SRGB_BLOCK => (8, 5, 16),
+        vk::Format::ASTC_8X6_UNORM_BLOCK | vk::Format::ASTC_8X6_SRGB_BLOCK => (8, 6, 16),
+        vk::Format::ASTC_8X8_UNORM_BLOCK | vk::Format::ASTC_8X8_SRGB_BLOCK => (8, 8, 16),
+        vk::Format::ASTC_10X5_UNORM_BLOCK | vk::Format::ASTC_10X5_SRGB_BLOCK => (10, 5, 16),
+        vk::Format::ASTC_10X6_UNORM_BLOCK | vk::Format::ASTC_10X6_SRGB_BLOCK => (10, 6, 16),
+        vk::Format::ASTC_10X8_UNORM_BLOCK | vk::Format::ASTC_10X8_SRGB_BLOCK => (10, 8, 16),
+        vk::Format::ASTC_10X10_UNORM_BLOCK | vk::Format::ASTC_10X10_SRGB_BLOCK => (10, 10, 16),
+        vk::Format::ASTC_12X10_UNORM_BLOCK | vk::Format::ASTC_12X10_SRGB_BLOCK => (12, 10, 16),
+        vk::Format::ASTC_12X12_UNORM_BLOCK | vk::Format::ASTC_12X12_SRGB_BLOCK => (12, 12, 16),
+        _ => (1, 1, format_texel_size(format)?),
+    };
+    Ok(block)
+}
+
+/// The size in bytes of a single image of the given dimensions
+pub fn format_image_size(format: vk::Format, width: u32, height: u32) -> Result<usize> {
+    let (block_width, block_height, block_size) = format_block_size(format)?;
+    // Computed in 64 bits so that large images from texture files cannot overflow
+    let blocks_wide = (width as u64 + block_width as u64 - 1) / block_width as u64;
+    let blocks_high = (height as u64 + block_height as u64 - 1) / block_height as u64;
+    let size = (blocks_wide * blocks_high)
+        .checked_mul(block_size as u64)
+        .and_then(|size| usize::try_from(size).ok())
+        .context("Image size does not fit into memory!")?;
+    Ok(size)
+}
+
+/// Buffer offsets of copies to and from images of the format must be multiples of this,
+/// which is the least common multiple of the texel block size and four bytes
+pub fn format_copy_alignment(format: vk::Format) -> Result<usize> {
+    let (_, _, block_size) = format_block_size(format)?;
+    let block_size = block_size as usize;
+    Ok(block_size * 4 / greatest_common_divisor(block_size, 4))
+}
+
+fn greatest_common_divisor(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        greatest_common_divisor(b, a % b)
+    }
+}
+
+/// The size in bytes of a single texel of an uncompressed color format
+pub fn format_texel_size(format: vk::Format) -> Result<u32> {
+    let size = match format {
+        vk::Format::R8_UNORM | vk::Format::R8_SRGB => 1,
+        vk::Format::R8G8_UNORM | vk::Format::R8G8_SRGB | vk::Format::R16_SFLOAT => 2,
+        vk::Format::R8G8B8_UNORM
+        | vk::Format::R8G8B8_SRGB
+        | vk::Format::B8G8R8_UNORM
+        | vk::Format::B8G8R8_SRGB => 3,
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::A2B10G10R10_UNORM_PACK32
+        | vk::Format::A2R10G10B10_UNORM_PACK32
+        | vk::Format::R16G16_SFLOAT
+        | vk::Format::R32_SFLOAT => 4,
+        vk::Format::R16G16B16_UNORM | vk::Format::R16G16B16_SFLOAT => 6,
+        vk::Format::R16G16B16A16_UNORM
+        | vk::Format::R16G16B16A16_SFLOAT
+        | vk::Format::R32G32_SFLOAT => 8,
+        vk::Format::R32G32B32_SFLOAT => 12,
+        vk::Format::R32G32B32A32_SFLOAT => 16,
+        _ => bail!("Texel size of format {:?} is unknown!", format),
+    };
+    Ok(size)
+}
+
 pub fn transition_image(
     image: vk::Image,
     pool: &CommandPool,
     info: &ImageLayoutTransition,
 ) -> Result<()> {
+    pool.transition_image_layout(&image_layout_barrier(image, info)?)
+}
+
+pub fn image_layout_barrier(
+    image: vk::Image,
+    info: &ImageLayoutTransition,
+) -> Result<PipelineBarrier> {
     let subresource_range = vk::ImageSubresourceRange::builder()
         .aspect_mask(vk::ImageAspectFlags::COLOR)
         .base_mip_level(info.base_mip_level)
@@ -208,14 +882,12 @@ pub fn transition_image(
         .src_access_mask(info.src_access_mask)
         .dst_access_mask(info.dst_access_mask)
         .build();
-    let pipeline_barrier_info = PipelineBarrierBuilder::default()
+    PipelineBarrierBuilder::default()
         .src_stage_mask(info.src_stage_mask)
         .dst_stage_mask(info.dst_stage_mask)
         .image_memory_barriers(vec![image_barrier])
         .build()
-        .map_err(|error| anyhow!("{}", error))?;
-    pool.transition_image_layout(&pipeline_barrier_info)?;
-    Ok(())
+        .map_err(|error| anyhow!("{}", error))
 }
 
 pub trait Image {
@@ -232,9 +904,16 @@ impl Image for RawImage {
 
 pub struct AllocatedImage {
     pub handle: vk::Image,
+    pub format: vk::Format,
+    pub extent: vk::Extent3D,
+    pub mip_levels: u32,
+    pub array_layers: u32,
+    usage: vk::ImageUsageFlags,
+    category: MemoryCategory,
     allocation: vk_mem::Allocation,
-    allocation_info: vk_mem::AllocationInfo,
+    pub allocation_info: vk_mem::AllocationInfo,
     allocator: Arc<Allocator>,
+    device: Arc<Device>,
 }
 
 impl Image for AllocatedImage {
@@ -245,45 +924,285 @@ impl Image for AllocatedImage {
 
 impl AllocatedImage {
     pub fn new(
+        device: Arc<Device>,
         allocator: Arc<Allocator>,
         allocation_create_info: &vk_mem::AllocationCreateInfo,
         image_create_info: &vk::ImageCreateInfoBuilder,
     ) -> Result<Self> {
         let (handle, allocation, allocation_info) =
             allocator.create_image(image_create_info, allocation_create_info)?;
+        device
+            .memory_usage()
+            .allocate(MemoryCategory::Other, allocation_info.get_size() as _);
 
         let texture = Self {
             handle,
+            format: image_create_info.format,
+            extent: image_create_info.extent,
+            mip_levels: image_create_info.mip_levels,
+            array_layers: image_create_info.array_layers,
+            usage: image_create_info.usage,
+            category: MemoryCategory::Other,
             allocation,
             allocation_info,
             allocator,
+            device,
         };
 
         Ok(texture)
     }
 
-    pub fn upload_data(
+    /// Moves the allocation to the category it is accounted under
+    pub fn set_memory_category(&mut self, category: MemoryCategory) {
+        let size = self.allocation_info.get_size() as vk::DeviceSize;
+        let memory_usage = self.device.memory_usage();
+        memory_usage.free(self.category, size);
+        memory_usage.allocate(category, size);
+        self.category = category;
+    }
+
+    /// Copies the base mip level back to host memory.
+    /// The image is expected to be in the layout that sampled images rest in.
+    pub fn download(&self, context: &Context, pool: &CommandPool) -> Result<ImageDescription> {
+        self.download_from_layout(context, pool, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+    }
+
+    /// Copies the base mip level back to host memory, returning the image to its layout afterwards.
+    /// Rows are tightly packed and BGRA pixels are reordered to RGBA.
+    pub fn download_from_layout(
+        &self,
+        context: &Context,
+        pool: &CommandPool,
+        layout: vk::ImageLayout,
+    ) -> Result<ImageDescription> {
+        let texel_size = format_texel_size(self.format)?;
+        let (width, height) = (self.extent.width, self.extent.height);
+        let size = (width as vk::DeviceSize * height as vk::DeviceSize)
+            .checked_mul(texel_size as _)
+            .context("Image is too large to read back!")?;
+        let readback =
+            ReadbackBuffer::new(context.device.clone(), context.allocator.clone(), size)?;
+
+        let to_transfer_src = ImageLayoutTransitionBuilder::default()
+            .old_layout(layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .src_stage_mask(vk::PipelineStageFlags::ALL_COMMANDS)
+            .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+            .build()
+            .map_err(|error| anyhow!("{}", error))?;
+        let restore_layout = ImageLayoutTransitionBuilder::default()
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(layout)
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+            .src_stage_mask(vk::PipelineStageFlags::TRANSFER)
+            .dst_stage_mask(vk::PipelineStageFlags::ALL_COMMANDS)
+            .build()
+            .map_err(|error| anyhow!("{}", error))?;
+        let to_transfer_src = image_layout_barrier(self.handle, &to_transfer_src)?;
+        let restore_layout = image_layout_barrier(self.handle, &restore_layout)?;
+        let host_visible = readback.host_read_barrier()?;
+
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1)
+            .build();
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(width)
+            .buffer_image_height(height)
+            .image_subresource(subresource)
+            .image_offset(vk::Offset3D::default())
+            .image_extent(vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            })
+            .build();
+        let copy_info = ImageToBufferCopyBuilder::default()
+            .source(self.handle)
+            .destination(readback.handle())
+            .regions(vec![region])
+            .build()
+            .map_err(|error| anyhow!("{}", error))?;
+
+        pool.execute_once(|command_buffer| {
+            self.device
+                .pipeline_barrier(command_buffer, &to_transfer_src);
+            self.device.copy_image_to_buffer(command_buffer, &copy_info);
+            self.device
+                .pipeline_barrier(command_buffer, &restore_layout);
+            self.device.pipeline_barrier(command_buffer, &host_visible);
+            Ok(())
+        })?;
+
+        let mut description = ImageDescription {
+            format: self.format,
+            width,
+            height,
+            depth: 1,
+            pixels: readback.read()?,
+            mip_levels: 1,
+            array_layers: 1,
+            mip_offsets: Vec::new(),
+            mip_filter: MipmapFilter::default(),
+            color_space: ColorSpace::of_format(self.format),
+        };
+        description.convert_bgra_formats();
+        Ok(description)
+    }
+
+    /// Copies every mip level and layer back to host memory, returning the image to its layout afterwards.
+    /// Levels are packed the way precomputed mips are, so the result can be uploaded or saved as is.
+    pub fn download_levels(
         &self,
         context: &Context,
         pool: &CommandPool,
+        layout: vk::ImageLayout,
+    ) -> Result<ImageDescription> {
+        let mut description =
+            ImageDescription::empty(self.extent.width, self.extent.height, self.format);
+        description.depth = self.extent.depth;
+        description.mip_levels = self.mip_levels;
+        description.array_layers = self.array_layers;
+        let (mip_offsets, size) = description.packed_level_offsets()?;
+        let readback =
+            ReadbackBuffer::new(context.device.clone(), context.allocator.clone(), size as _)?;
+
+        let to_transfer_src = ImageLayoutTransitionBuilder::default()
+            .level_count(self.mip_levels)
+            .layer_count(self.array_layers)
+            .old_layout(layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .src_stage_mask(vk::PipelineStageFlags::ALL_COMMANDS)
+            .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+            .build()
+            .map_err(|error| anyhow!("{}", error))?;
+        let restore_layout = ImageLayoutTransitionBuilder::default()
+            .level_count(self.mip_levels)
+            .layer_count(self.array_layers)
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(layout)
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+            .src_stage_mask(vk::PipelineStageFlags::TRANSFER)
+            .dst_stage_mask(vk::PipelineStageFlags::ALL_COMMANDS)
+            .build()
+            .map_err(|error| anyhow!("{}", error))?;
+        let to_transfer_src = image_layout_barrier(self.handle, &to_transfer_src)?;
+        let restore_layout = image_layout_barrier(self.handle, &restore_layout)?;
+        let host_visible = readback.host_read_barrier()?;
+
+        let regions = mip_offsets
+            .iter()
+            .enumerate()
+            .map(|(level, offset)| {
+                let (width, height) = description.mip_extent(level as _);
+                let subresource = vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(level as _)
+                    .layer_count(self.array_layers)
+                    .build();
+                vk::BufferImageCopy::builder()
+                    .buffer_offset(*offset as _)
+                    .buffer_row_length(0)
+                    .buffer_image_height(0)
+                    .image_subresource(subresource)
+                    .image_offset(vk::Offset3D::default())
+                    .image_extent(vk::Extent3D {
+                        width,
+                        height,
+                        depth: description.mip_depth(level as _),
+                    })
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let copy_info = ImageToBufferCopyBuilder::default()
+            .source(self.handle)
+            .destination(readback.handle())
+            .regions(regions)
+            .build()
+            .map_err(|error| anyhow!("{}", error))?;
+
+        pool.execute_once(|command_buffer| {
+            self.device
+                .pipeline_barrier(command_buffer, &to_transfer_src);
+            self.device.copy_image_to_buffer(command_buffer, &copy_info);
+            self.device
+                .pipeline_barrier(command_buffer, &restore_layout);
+            self.device.pipeline_barrier(command_buffer, &host_visible);
+            Ok(())
+        })?;
+
+        description.pixels = readback.read()?;
+        description.mip_offsets = mip_offsets;
+        Ok(description)
+    }
+
+    /// Uploads the pixels and generates mipmaps in a single submission, then waits for it to finish
+    pub fn upload_data(&self, context: &Context, description: &ImageDescription) -> Result<()> {
+        let mut batch = UploadBatch::new(context)?;
+        self.record_upload(&mut batch, context, description)?;
+        batch.submit()?.wait()
+    }
+
+    pub fn record_upload(
+        &self,
+        batch: &mut UploadBatch,
+        context: &Context,
         description: &ImageDescription,
     ) -> Result<()> {
-        let buffer = CpuToGpuBuffer::staging_buffer(
-            self.allocator.clone(),
-            self.allocation_info.get_size() as _,
-        )?;
-        buffer.upload_data(&description.pixels, 0)?;
-        self.transition_base_to_transfer_dst(pool, description.mip_levels)?;
-        self.copy_to_gpu_buffer(pool, buffer.handle(), description)?;
-        context.ensure_linear_blitting_supported(description.format)?;
-        self.generate_mipmaps(pool, description)?;
-        self.transition_base_to_shader_read(pool, description.mip_levels - 1)?;
-        Ok(())
+        if description.has_precomputed_mips() {
+            ensure!(
+                description.mip_offsets.len() == description.mip_levels as usize,
+                "Image has {} precomputed mip levels but expects {}!",
+                description.mip_offsets.len(),
+                description.mip_levels
+            );
+        }
+        context.ensure_sampling_supported(description.format)?;
+
+        let staged = batch.stage_pixels(&description.pixels, description.format)?;
+        self.transition_base_to_transfer_dst(batch, description.mip_levels)?;
+        self.copy_to_gpu_buffer(batch, &staged, description)?;
+        self.transfer_ownership(batch, description.mip_levels)?;
+        self.record_mipmaps(batch, context, description)
     }
 
-    fn transition_base_to_transfer_dst(&self, pool: &CommandPool, level_count: u32) -> Result<()> {
+    /// Fills in the mip levels the description does not provide, on the graphics queue.
+    /// Expects every level in `TRANSFER_DST_OPTIMAL` with the provided levels written,
+    /// and leaves every level in `SHADER_READ_ONLY_OPTIMAL`.
+    pub fn record_mipmaps(
+        &self,
+        batch: &mut UploadBatch,
+        context: &Context,
+        description: &ImageDescription,
+    ) -> Result<()> {
+        match description.mipmap_method(context)? {
+            // Precomputed mips are copied as they are, so there is nothing to generate
+            MipmapMethod::Precomputed => {
+                self.transition_base_to_shader_read(batch, 0, description.mip_levels)
+            }
+            MipmapMethod::Blit => {
+                self.generate_mipmaps(batch, description)?;
+                self.transition_base_to_shader_read(batch, description.mip_levels - 1, 1)
+            }
+            MipmapMethod::Compute => {
+                context
+                    .mipmap_generator()?
+                    .record(batch, self, description.mip_filter)
+            }
+        }
+    }
+
+    fn transition_base_to_transfer_dst(&self, batch: &UploadBatch, level_count: u32) -> Result<()> {
         let transition = ImageLayoutTransitionBuilder::default()
             .level_count(level_count)
+            .layer_count(self.array_layers)
             .old_layout(vk::ImageLayout::UNDEFINED)
             .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
             .src_access_mask(vk::AccessFlags::empty())
@@ -292,16 +1211,32 @@ impl AllocatedImage {
             .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
             .build()
             .map_err(|error| anyhow!("{}", error))?;
-        transition_image(self.handle, pool, &transition)
+        batch.transition_image(self.handle, &transition)
+    }
+
+    fn transfer_ownership(&self, batch: &UploadBatch, level_count: u32) -> Result<()> {
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(level_count)
+            .layer_count(self.array_layers)
+            .build();
+        batch.transfer_image_ownership(
+            self.handle,
+            subresource_range,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        )
     }
 
     fn transition_base_to_shader_read(
         &self,
-        pool: &CommandPool,
+        batch: &UploadBatch,
         base_mip_level: u32,
+        level_count: u32,
     ) -> Result<()> {
         let transition = ImageLayoutTransitionBuilder::default()
             .base_mip_level(base_mip_level)
+            .level_count(level_count)
+            .layer_count(self.array_layers)
             .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
             .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
             .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
@@ -310,17 +1245,18 @@ impl AllocatedImage {
             .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
             .build()
             .map_err(|error| anyhow!("{}", error))?;
-        transition_image(self.handle, pool, &transition)
+        batch.graphics_transition_image(self.handle, &transition)
     }
 
     fn transition_mip_transfer_dst_to_src(
         &self,
-        pool: &CommandPool,
+        batch: &UploadBatch,
         base_mip_level: u32,
     ) -> Result<()> {
         let transition = ImageLayoutTransitionBuilder::default()
             .base_mip_level(base_mip_level)
             .level_count(1)
+            .layer_count(self.array_layers)
             .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
             .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
             .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
@@ -329,12 +1265,17 @@ impl AllocatedImage {
             .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
             .build()
             .map_err(|error| anyhow!("{}", error))?;
-        transition_image(self.handle, pool, &transition)
+        batch.graphics_transition_image(self.handle, &transition)
     }
 
-    fn transition_mip_to_shader_read(&self, pool: &CommandPool, base_mip_level: u32) -> Result<()> {
+    fn transition_mip_to_shader_read(
+        &self,
+        batch: &UploadBatch,
+        base_mip_level: u32,
+    ) -> Result<()> {
         let transition = ImageLayoutTransitionBuilder::default()
             .base_mip_level(base_mip_level)
+            .layer_count(self.array_layers)
             .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
             .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
             .src_access_mask(vk::AccessFlags::TRANSFER_READ)
@@ -343,54 +1284,206 @@ impl AllocatedImage {
             .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
             .build()
             .map_err(|error| anyhow!("{}", error))?;
-        transition_image(self.handle, pool, &transition)
+        batch.graphics_transition_image(self.handle, &transition)
     }
 
     fn copy_to_gpu_buffer(
         &self,
-        pool: &CommandPool,
-        buffer: vk::Buffer,
+        batch: &UploadBatch,
+        staged: &StagedRegion,
         description: &ImageDescription,
     ) -> Result<()> {
-        let extent = vk::Extent3D::builder()
-            .width(description.width)
-            .height(description.height)
-            .depth(1)
-            .build();
-        let subresource = vk::ImageSubresourceLayers::builder()
-            .aspect_mask(vk::ImageAspectFlags::COLOR)
-            .layer_count(1)
-            .build();
-        let region = vk::BufferImageCopy::builder()
-            .buffer_offset(0)
-            .buffer_row_length(0)
-            .buffer_image_height(0)
-            .image_subresource(subresource)
-            .image_offset(vk::Offset3D::default())
-            .image_extent(extent)
-            .build();
+        // Each level holds every layer back to back, so one region covers all of its layers
+        let level_offsets = if description.has_precomputed_mips() {
+            description.mip_offsets.clone()
+        } else {
+            vec![0]
+        };
+        let regions = level_offsets
+            .iter()
+            .enumerate()
+            .map(|(level, offset)| {
+                let (width, height) = description.mip_extent(level as _);
+                let extent = vk::Extent3D::builder()
+                    .width(width)
+                    .height(height)
+                    .depth(description.mip_depth(level as _))
+                    .build();
+                let subresource = vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(level as _)
+                    .layer_count(description.array_layers)
+                    .build();
+                vk::BufferImageCopy::builder()
+                    .buffer_offset(staged.offset + *offset as vk::DeviceSize)
+                    .buffer_row_length(0)
+                    .buffer_image_height(0)
+                    .image_subresource(subresource)
+                    .image_offset(vk::Offset3D::default())
+                    .image_extent(extent)
+                    .build()
+            })
+            .collect::<Vec<_>>();
         let copy_info = BufferToImageCopyBuilder::default()
-            .source(buffer)
+            .source(staged.buffer)
             .destination(self.handle)
-            .regions(vec![region])
+            .regions(regions)
             .build()
             .map_err(|error| anyhow!("{}", error))?;
-        pool.copy_buffer_to_image(&copy_info)?;
+        batch.copy_buffer_to_image(&copy_info);
         Ok(())
     }
 
+    /// A view of every layer of one mip level, for compute shaders to write to
+    pub fn storage_view(&self, level: u32) -> Result<ImageView> {
+        self.level_view(level, storage_format(self.format))
+    }
+
+    /// A view of every layer of one mip level as a 2D array
+    pub fn level_view(&self, level: u32, format: vk::Format) -> Result<ImageView> {
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(level)
+            .level_count(1)
+            .layer_count(self.array_layers);
+        self.create_view(
+            vk::ImageViewType::TYPE_2D_ARRAY,
+            format,
+            subresource_range.build(),
+        )
+    }
+
+    /// A view of every level and layer in the image's own format
+    pub fn view(&self, view_type: vk::ImageViewType) -> Result<ImageView> {
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(self.mip_levels)
+            .layer_count(self.array_layers);
+        self.create_view(view_type, self.format, subresource_range.build())
+    }
+
+    /// A view of the given levels and layers, in the image's format or one compatible with it
+    pub fn create_view(
+        &self,
+        view_type: vk::ImageViewType,
+        format: vk::Format,
+        subresource_range: vk::ImageSubresourceRange,
+    ) -> Result<ImageView> {
+        let mut usage_info = vk::ImageViewUsageCreateInfo::builder()
+            .usage(self.usage & !vk::ImageUsageFlags::STORAGE);
+        let mut create_info = vk::ImageViewCreateInfo::builder()
+            .image(self.handle)
+            .view_type(view_type)
+            .format(format)
+            .components(vk::ComponentMapping::default())
+            .subresource_range(subresource_range);
+
+        // Views in formats that cannot be used for storage, such as sRGB,
+        // must not inherit the storage usage that compute mipmap generation adds
+        if self.usage.contains(vk::ImageUsageFlags::STORAGE) && storage_format(format) != format {
+            create_info = create_info.push_next(&mut usage_info);
+        }
+
+        ImageView::new(self.device.clone(), create_info)
+    }
+
+    /// Moves the base level into the layout compute shaders write in,
+    /// and the rest of the levels to where `record_mipmaps` expects them
+    pub fn transition_for_compute_write(
+        &self,
+        batch: &UploadBatch,
+        level_count: u32,
+    ) -> Result<()> {
+        let base_to_general = ImageLayoutTransitionBuilder::default()
+            .layer_count(self.array_layers)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .src_stage_mask(vk::PipelineStageFlags::TOP_OF_PIPE)
+            .dst_stage_mask(vk::PipelineStageFlags::COMPUTE_SHADER)
+            .build()
+            .map_err(|error| anyhow!("{}", error))?;
+        batch.graphics_transition_image(self.handle, &base_to_general)?;
+        if level_count < 2 {
+            return Ok(());
+        }
+        let mips_to_transfer_dst = ImageLayoutTransitionBuilder::default()
+            .base_mip_level(1)
+            .level_count(level_count - 1)
+            .layer_count(self.array_layers)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .src_stage_mask(vk::PipelineStageFlags::TOP_OF_PIPE)
+            .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+            .build()
+            .map_err(|error| anyhow!("{}", error))?;
+        batch.graphics_transition_image(self.handle, &mips_to_transfer_dst)
+    }
+
+    /// Hands the base level written by a compute shader over to `record_mipmaps`
+    pub fn transition_compute_written_to_transfer_dst(&self, batch: &UploadBatch) -> Result<()> {
+        let transition = ImageLayoutTransitionBuilder::default()
+            .layer_count(self.array_layers)
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::TRANSFER_WRITE)
+            .src_stage_mask(vk::PipelineStageFlags::COMPUTE_SHADER)
+            .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+            .build()
+            .map_err(|error| anyhow!("{}", error))?;
+        batch.graphics_transition_image(self.handle, &transition)
+    }
+
+    /// Moves every level into the layout compute shaders write in
+    pub fn transition_levels_to_general(&self, batch: &UploadBatch) -> Result<()> {
+        let transition = ImageLayoutTransitionBuilder::default()
+            .level_count(self.mip_levels)
+            .layer_count(self.array_layers)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .src_stage_mask(vk::PipelineStageFlags::TOP_OF_PIPE)
+            .dst_stage_mask(vk::PipelineStageFlags::COMPUTE_SHADER)
+            .build()
+            .map_err(|error| anyhow!("{}", error))?;
+        batch.graphics_transition_image(self.handle, &transition)
+    }
+
+    /// Makes every level written by compute shaders readable by later shaders
+    pub fn transition_general_to_shader_read(&self, batch: &UploadBatch) -> Result<()> {
+        let transition = ImageLayoutTransitionBuilder::default()
+            .level_count(self.mip_levels)
+            .layer_count(self.array_layers)
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .src_stage_mask(vk::PipelineStageFlags::COMPUTE_SHADER)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            )
+            .build()
+            .map_err(|error| anyhow!("{}", error))?;
+        batch.graphics_transition_image(self.handle, &transition)
+    }
+
     pub fn generate_mipmaps(
         &self,
-        pool: &CommandPool,
+        batch: &UploadBatch,
         description: &ImageDescription,
     ) -> Result<()> {
         let mut width = description.width as i32;
         let mut height = description.height as i32;
         for level in 1..description.mip_levels {
-            self.transition_mip_transfer_dst_to_src(pool, level - 1)?;
+            self.transition_mip_transfer_dst_to_src(batch, level - 1)?;
             let dimensions = MipmapBlitDimensions::new(width, height);
-            self.blit_mipmap(pool, &dimensions, level)?;
-            self.transition_mip_to_shader_read(pool, level - 1)?;
+            self.blit_mipmap(batch, &dimensions, level)?;
+            self.transition_mip_to_shader_read(batch, level - 1)?;
             width = dimensions.next_width;
             height = dimensions.next_height;
         }
@@ -399,20 +1492,20 @@ impl AllocatedImage {
 
     fn blit_mipmap(
         &self,
-        pool: &CommandPool,
+        batch: &UploadBatch,
         dimensions: &MipmapBlitDimensions,
         level: u32,
     ) -> Result<()> {
         let src_subresource = vk::ImageSubresourceLayers::builder()
             .aspect_mask(vk::ImageAspectFlags::COLOR)
             .mip_level(level - 1)
-            .layer_count(1)
+            .layer_count(self.array_layers)
             .build();
 
         let dst_subresource = vk::ImageSubresourceLayers::builder()
             .aspect_mask(vk::ImageAspectFlags::COLOR)
             .mip_level(level)
-            .layer_count(1)
+            .layer_count(self.array_layers)
             .build();
 
         let regions = vk::ImageBlit::builder()
@@ -432,13 +1525,21 @@ impl AllocatedImage {
             .build()
             .map_err(|error| anyhow!("{}", error))?;
 
-        pool.blit_image(&blit_image_info)
+        batch.blit_image(&blit_image_info);
+        Ok(())
     }
 }
 
 impl Drop for AllocatedImage {
     fn drop(&mut self) {
-        self.allocator.destroy_image(self.handle, &self.allocation);
+        self.device
+            .memory_usage()
+            .free(self.category, self.allocation_info.get_size() as _);
+        let handle = self.handle;
+        let allocation = self.allocation;
+        let allocator = self.allocator.clone();
+        self.device
+            .defer_destruction(move |_| allocator.destroy_image(handle, &allocation));
     }
 }
 
@@ -457,48 +1558,9 @@ impl ImageView {
 
 impl Drop for ImageView {
     fn drop(&mut self) {
-        unsafe {
-            self.device.handle.destroy_image_view(self.handle, None);
-        }
-    }
-}
-
-pub struct Sampler {
-    pub handle: vk::Sampler,
-    device: Arc<Device>,
-}
-
-impl Sampler {
-    pub fn new(device: Arc<Device>, create_info: vk::SamplerCreateInfoBuilder) -> Result<Self> {
-        let handle = unsafe { device.handle.create_sampler(&create_info, None) }?;
-        let sampler = Self { handle, device };
-        Ok(sampler)
-    }
-
-    pub fn default(device: Arc<Device>) -> Result<Self> {
-        let sampler_info = vk::SamplerCreateInfo::builder()
-            .mag_filter(vk::Filter::LINEAR)
-            .min_filter(vk::Filter::LINEAR)
-            .address_mode_u(vk::SamplerAddressMode::REPEAT)
-            .address_mode_v(vk::SamplerAddressMode::REPEAT)
-            .address_mode_w(vk::SamplerAddressMode::REPEAT)
-            .anisotropy_enable(true)
-            .max_anisotropy(16.0)
-            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
-            .unnormalized_coordinates(false)
-            .compare_enable(false)
-            .compare_op(vk::CompareOp::ALWAYS)
-            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-            .mip_lod_bias(0.0)
-            .min_lod(0.0)
-            .max_lod(1.0);
-        Self::new(device, sampler_info)
-    }
-}
-
-impl Drop for Sampler {
-    fn drop(&mut self) {
-        unsafe { self.device.handle.destroy_sampler(self.handle, None) };
+        let handle = self.handle;
+        self.device
+            .defer_destruction(move |device| unsafe { device.destroy_image_view(handle, None) });
     }
 }
 
@@ -548,36 +1610,44 @@ pub struct Texture {
 }
 
 impl Texture {
-    pub fn new(
-        context: &Context,
-        command_pool: &CommandPool,
-        description: &ImageDescription,
-    ) -> Result<Self> {
-        let image = description.as_image(context.allocator.clone())?;
-        image.upload_data(context, command_pool, description)?;
-        let view = Self::image_view(context.device.clone(), &image, description)?;
+    pub fn new(context: &Context, description: &ImageDescription) -> Result<Self> {
+        let image = description.as_image(context)?;
+        image.upload_data(context, description)?;
+        let view = Self::image_view(&image, description)?;
         let texture = Self { image, view };
         Ok(texture)
     }
 
-    fn image_view(
-        device: Arc<Device>,
-        image: &AllocatedImage,
+    /// Starts uploading the texture on the transfer queue without waiting for it to finish
+    pub fn new_async(
+        context: &Context,
         description: &ImageDescription,
-    ) -> Result<ImageView> {
+    ) -> Result<AsyncUpload<Self>> {
+        let image = description.as_image(context)?;
+        let mut batch = UploadBatch::asynchronous(context)?;
+        image.record_upload(&mut batch, context, description)?;
+        let view = Self::image_view(&image, description)?;
+        let pending = batch.submit()?;
+        Ok(AsyncUpload::new(Self { image, view }, pending))
+    }
+
+    /// Creates the image without uploading anything, for compute shaders to fill in
+    pub fn new_storage(context: &Context, description: &ImageDescription) -> Result<Self> {
+        let image = description.as_storage_image(context)?;
+        let view = Self::image_view(&image, description)?;
+        Ok(Self { image, view })
+    }
+
+    fn image_view(image: &AllocatedImage, description: &ImageDescription) -> Result<ImageView> {
         let subresource_range = vk::ImageSubresourceRange::builder()
             .aspect_mask(vk::ImageAspectFlags::COLOR)
             .layer_count(1)
             .level_count(description.mip_levels);
-
-        let create_info = vk::ImageViewCreateInfo::builder()
-            .image(image.handle)
-            .view_type(vk::ImageViewType::TYPE_2D)
-            .format(description.format)
-            .components(vk::ComponentMapping::default())
-            .subresource_range(subresource_range.build());
-
-        ImageView::new(device, create_info)
+        image.create_view(
+            vk::ImageViewType::TYPE_2D,
+            description.format,
+            subresource_range.build(),
+        )
     }
 }
 
@@ -587,37 +1657,192 @@ pub struct Cubemap {
 }
 
 impl Cubemap {
-    pub fn new(
-        context: &Context,
-        command_pool: &CommandPool,
-        description: &ImageDescription,
-    ) -> Result<Self> {
-        let image = description.as_cubemap(context.allocator.clone())?;
+    pub const EQUIRECTANGULAR_SHADER_PATH: &'static str =
+        "assets/shaders/environment/equirectangular_to_cube.comp.spv";
+
+    /// Expects the pixels, if there are any, to hold all six faces as array layers
+    pub fn new(context: &Context, description: &ImageDescription) -> Result<Self> {
+        let image = description.as_cubemap(context)?;
         if !description.pixels.is_empty() {
-            image.upload_data(context, command_pool, description)?;
+            ensure!(
+                description.array_layers == 6,
+                "Cubemaps need six faces, but the image description has {} layers!",
+                description.array_layers
+            );
+            image.upload_data(context, description)?;
         }
-        let view = Self::image_view(context.device.clone(), &image, description)?;
+        let view = Self::image_view(&image, description)?;
         let texture = Self { image, view };
         Ok(texture)
     }
 
-    fn image_view(
-        device: Arc<Device>,
-        image: &AllocatedImage,
-        description: &ImageDescription,
-    ) -> Result<ImageView> {
+    /// Creates the cubemap without uploading anything, for compute shaders to fill in face by face
+    pub fn new_storage(context: &Context, description: &ImageDescription) -> Result<Self> {
+        let image = description.as_storage_cubemap(context)?;
+        let view = Self::image_view(&image, description)?;
+        Ok(Self { image, view })
+    }
+
+    /// Loads the faces in the order +X, -X, +Y, -Y, +Z, -Z, treating them as color.
+    /// Every face must be square and share the same size and format.
+    pub fn from_faces<P: AsRef<Path>>(context: &Context, paths: [P; 6]) -> Result<Self> {
+        let faces = paths
+            .iter()
+            .map(|path| ImageDescription::from_file(path.as_ref(), ColorSpace::Color))
+            .collect::<Result<Vec<_>>>()?;
+        let first = &faces[0];
+        ensure!(
+            first.width == first.height,
+            "Cubemap faces must be square, but are {}x{}!",
+            first.width,
+            first.height
+        );
+        Self::new(context, &ImageDescription::from_layers(&faces)?)
+    }
+
+    /// Loads an equirectangular HDR image and projects it onto the faces of a cubemap on the GPU
+    pub fn from_equirectangular<P: AsRef<Path>>(
+        context: &Context,
+        path: P,
+        face_size: u32,
+    ) -> Result<Self> {
+        // Only the base level is sampled, so the source does not need mipmaps
+        let mut equirectangular = ImageDescription::from_hdr(path)?;
+        equirectangular.mip_levels = 1;
+        let source = Texture::new(context, &equirectangular)?;
+
+        // Float formats are not guaranteed to support linear filtering
+        let filter = if context
+            .physical_device_format_properties(equirectangular.format)
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
+        {
+            vk::Filter::LINEAR
+        } else {
+            vk::Filter::NEAREST
+        };
+        let sampler_desc = SamplerDesc {
+            mag_filter: filter,
+            min_filter: filter,
+            ..Default::default()
+        };
+        let sampler = Sampler::from_desc(context.device.clone(), &sampler_desc)?;
+
+        let mut description =
+            ImageDescription::empty(face_size, face_size, vk::Format::R16G16B16A16_SFLOAT);
+        description.array_layers = 6;
+        let image = description.as_storage_cubemap(context)?;
+        let face_view = image.storage_view(0)?;
+        let kernel = ComputeKernel::new(
+            context.device.clone(),
+            Self::EQUIRECTANGULAR_SHADER_PATH,
+            &[
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::DescriptorType::STORAGE_IMAGE,
+            ],
+            0,
+        )?;
+
+        let mut batch = UploadBatch::new(context)?;
+        image.transition_for_compute_write(&batch, description.mip_levels)?;
+        kernel.dispatch(
+            &mut batch,
+            &[
+                KernelBinding::Sampled(source.view.handle, sampler.handle),
+                KernelBinding::Storage(face_view.handle),
+            ],
+            &[],
+            [work_group_count(face_size), work_group_count(face_size), 6],
+        )?;
+        image.transition_compute_written_to_transfer_dst(&batch)?;
+        image.record_mipmaps(&mut batch, context, &description)?;
+        batch.submit()?.wait()?;
+
+        let view = Self::image_view(&image, &description)?;
+        Ok(Self { image, view })
+    }
+
+    fn image_view(image: &AllocatedImage, description: &ImageDescription) -> Result<ImageView> {
         let subresource_range = vk::ImageSubresourceRange::builder()
             .aspect_mask(vk::ImageAspectFlags::COLOR)
             .layer_count(6)
             .level_count(description.mip_levels);
+        image.create_view(
+            vk::ImageViewType::CUBE,
+            description.format,
+            subresource_range.build(),
+        )
+    }
+}
 
-        let create_info = vk::ImageViewCreateInfo::builder()
-            .image(image.handle)
-            .view_type(vk::ImageViewType::CUBE)
-            .format(description.format)
-            .components(vk::ComponentMapping::default())
-            .subresource_range(subresource_range.build());
+/// A 2D array texture with a mip chain per layer, sampled as a `sampler2DArray`
+pub struct TextureArray {
+    pub image: AllocatedImage,
+    pub view: ImageView,
+}
+
+impl TextureArray {
+    /// Expects the pixels to hold every layer, as `ImageDescription::from_layers` produces
+    pub fn new(context: &Context, description: &ImageDescription) -> Result<Self> {
+        let image = description.as_image(context)?;
+        image.upload_data(context, description)?;
+        let view = image.view(vk::ImageViewType::TYPE_2D_ARRAY)?;
+        Ok(Self { image, view })
+    }
+
+    /// Loads each file as a layer, in order.
+    /// Every file must share the same size and format.
+    pub fn from_files<P: AsRef<Path>>(
+        context: &Context,
+        paths: &[P],
+        color_space: ColorSpace,
+    ) -> Result<Self> {
+        let layers = paths
+            .iter()
+            .map(|path| ImageDescription::from_file(path.as_ref(), color_space))
+            .collect::<Result<Vec<_>>>()?;
+        Self::new(context, &ImageDescription::from_layers(&layers)?)
+    }
+}
+
+/// A volume texture without mipmaps, sampled as a `sampler3D`
+pub struct Texture3D {
+    pub image: AllocatedImage,
+    pub view: ImageView,
+}
+
+impl Texture3D {
+    pub fn new(context: &Context, description: &ImageDescription) -> Result<Self> {
+        let image = description.as_volume(context)?;
+        image.upload_data(context, description)?;
+        let view = image.view(vk::ImageViewType::TYPE_3D)?;
+        Ok(Self { image, view })
+    }
 
-        ImageView::new(device, create_info)
+    /// Uploads tightly packed slices, stored front to back
+    pub fn from_raw(
+        context: &Context,
+        width: u32,
+        height: u32,
+        depth: u32,
+        format: vk::Format,
+        pixels: &[u8],
+    ) -> Result<Self> {
+        let description = ImageDescription::volume(width, height, depth, format, pixels.to_vec())?;
+        Self::new(context, &description)
+    }
+
+    /// Loads each file as a slice, front to back.
+    /// Every file must share the same size and format.
+    pub fn from_slices<P: AsRef<Path>>(
+        context: &Context,
+        paths: &[P],
+        color_space: ColorSpace,
+    ) -> Result<Self> {
+        let slices = paths
+            .iter()
+            .map(|path| ImageDescription::from_file(path.as_ref(), color_space))
+            .collect::<Result<Vec<_>>>()?;
+        Self::new(context, &ImageDescription::from_slices(&slices)?)
     }
 }
@@ -0,0 +1,261 @@
+use crate::vulkan::{
+    byte_slice_from,
+    core::{
+        linear_format, work_group_count, AllocatedImage, ComputeKernel, Context, Device,
+        ImageLayoutTransitionBuilder, KernelBinding, UploadBatch,
+    },
+};
+use anyhow::{anyhow, Result};
+use ash::vk;
+use std::sync::Arc;
+
+/// How the mip levels missing from an image description are filtered on upload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MipmapFilter {
+    /// Linear blits, or a box filter in a compute shader for formats that cannot be blitted
+    Linear,
+    /// A 2x2 box filter in a compute shader, averaging sRGB images in linear space
+    Box,
+    /// A Kaiser windowed sinc in a compute shader, which keeps more detail than a box filter
+    Kaiser,
+}
+
+impl Default for MipmapFilter {
+    fn default() -> Self {
+        MipmapFilter::Linear
+    }
+}
+
+/// How an upload fills in the mip levels of an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MipmapMethod {
+    /// Every level is copied from the description
+    Precomputed,
+    Blit,
+    Compute,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+struct DownsamplePushConstantBlock {
+    filter_mode: u32,
+    encode_srgb: u32,
+    clamp_negative: u32,
+}
+
+/// Generates mip levels with a compute shader,
+/// for formats that cannot be linearly blitted or when a better filter than a blit is wanted
+pub struct MipmapGenerator {
+    kernel: ComputeKernel,
+}
+
+impl MipmapGenerator {
+    pub const SHADER_PATH: &'static str = "assets/shaders/mipmap/downsample.comp.spv";
+
+    pub fn new(device: Arc<Device>) -> Result<Self> {
+        let kernel = ComputeKernel::new(
+            device,
+            Self::SHADER_PATH,
+            &[
+                vk::DescriptorType::SAMPLED_IMAGE,
+                vk::DescriptorType::STORAGE_IMAGE,
+            ],
+            std::mem::size_of::<DownsamplePushConstantBlock>() as _,
+        )?;
+        Ok(Self { kernel })
+    }
+
+    /// Whether the format can be sampled, and written through its storage format.
+    /// Integer formats are never supported, since the shader averages texels as floats.
+    pub fn supports_format(context: &Context, format: vk::Format) -> bool {
+        if is_integer_format(format) {
+            return false;
+        }
+        let sampled = context
+            .physical_device_format_properties(format)
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE);
+        let storage = context
+            .physical_device_format_properties(storage_format(format))
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::STORAGE_IMAGE);
+        context.enabled_features.storage_image_write_without_format && sampled && storage
+    }
+
+    /// Records the downsampling of every level from the one above it on the graphics queue.
+    /// Expects every level in `TRANSFER_DST_OPTIMAL` with the base level written,
+    /// and leaves every level in `SHADER_READ_ONLY_OPTIMAL`.
+    pub fn record(
+        &self,
+        batch: &mut UploadBatch,
+        image: &AllocatedImage,
+        filter: MipmapFilter,
+    ) -> Result<()> {
+        let level_count = image.mip_levels;
+        if level_count < 2 {
+            return Ok(());
+        }
+        let storage_format = storage_format(image.format);
+
+        let base_to_shader_read = ImageLayoutTransitionBuilder::default()
+            .layer_count(image.array_layers)
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .src_stage_mask(vk::PipelineStageFlags::TRANSFER)
+            .dst_stage_mask(vk::PipelineStageFlags::COMPUTE_SHADER)
+            .build()
+            .map_err(|error| anyhow!("{}", error))?;
+        let mips_to_general = ImageLayoutTransitionBuilder::default()
+            .base_mip_level(1)
+            .level_count(level_count - 1)
+            .layer_count(image.array_layers)
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .src_stage_mask(vk::PipelineStageFlags::TOP_OF_PIPE)
+            .dst_stage_mask(vk::PipelineStageFlags::COMPUTE_SHADER)
+            .build()
+            .map_err(|error| anyhow!("{}", error))?;
+        batch.graphics_transition_image(image.handle, &base_to_shader_read)?;
+        batch.graphics_transition_image(image.handle, &mips_to_general)?;
+
+        let push_constants = DownsamplePushConstantBlock {
+            filter_mode: match filter {
+                MipmapFilter::Kaiser => 1,
+                _ => 0,
+            },
+            encode_srgb: (storage_format != image.format) as u32,
+            clamp_negative: !is_signed_format(image.format) as u32,
+        };
+
+        for level in 1..level_count {
+            let source = image.level_view(level - 1, image.format)?;
+            let destination = image.storage_view(level)?;
+            let width = (image.extent.width >> level).max(1);
+            let height = (image.extent.height >> level).max(1);
+            self.kernel.dispatch(
+                batch,
+                &[
+                    KernelBinding::Texture(source.handle),
+                    KernelBinding::Storage(destination.handle),
+                ],
+                unsafe { byte_slice_from(&push_constants) },
+                [
+                    work_group_count(width),
+                    work_group_count(height),
+                    image.array_layers,
+                ],
+            )?;
+
+            let level_to_shader_read = ImageLayoutTransitionBuilder::default()
+                .base_mip_level(level)
+                .layer_count(image.array_layers)
+                .old_layout(vk::ImageLayout::GENERAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .src_stage_mask(vk::PipelineStageFlags::COMPUTE_SHADER)
+                .dst_stage_mask(
+                    vk::PipelineStageFlags::COMPUTE_SHADER
+                        | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                )
+                .build()
+                .map_err(|error| anyhow!("{}", error))?;
+            batch.graphics_transition_image(image.handle, &level_to_shader_read)?;
+
+            // The views have to outlive the commands that use them
+            batch.retain(source);
+            batch.retain(destination);
+        }
+        Ok(())
+    }
+}
+
+/// The format that storage writes to an image go through.
+/// sRGB formats are rarely usable as storage images, so they are written as UNORM with the encoding done in the shader.
+pub fn storage_format(format: vk::Format) -> vk::Format {
+    linear_format(format)
+}
+
+fn is_integer_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::R8_UINT
+            | vk::Format::R8_SINT
+            | vk::Format::R8G8_UINT
+            | vk::Format::R8G8_SINT
+            | vk::Format::R8G8B8_UINT
+            | vk::Format::R8G8B8_SINT
+            | vk::Format::B8G8R8_UINT
+            | vk::Format::B8G8R8_SINT
+            | vk::Format::R8G8B8A8_UINT
+            | vk::Format::R8G8B8A8_SINT
+            | vk::Format::B8G8R8A8_UINT
+            | vk::Format::B8G8R8A8_SINT
+            | vk::Format::A8B8G8R8_UINT_PACK32
+            | vk::Format::A8B8G8R8_SINT_PACK32
+            | vk::Format::A2R10G10B10_UINT_PACK32
+            | vk::Format::A2R10G10B10_SINT_PACK32
+            | vk::Format::A2B10G10R10_UINT_PACK32
+            | vk::Format::A2B10G10R10_SINT_PACK32
+            | vk::Format::R16_UINT
+            | vk::Format::R16_SINT
+            | vk::Format::R16G16_UINT
+            | vk::Format::R16G16_SINT
+            | vk::Format::R16G16B16_UINT
+            | vk::Format::R16G16B16_SINT
+            | vk::Format::R16G16B16A16_UINT
+            | vk::Format::R16G16B16A16_SINT
+            | vk::Format::R32_UINT
+            | vk::Format::R32_SINT
+            | vk::Format::R32G32_UINT
+            | vk::Format::R32G32_SINT
+            | vk::Format::R32G32B32_UINT
+            | vk::Format::R32G32B32_SINT
+            | vk::Format::R32G32B32A32_UINT
+            | vk::Format::R32G32B32A32_SINT
+            | vk::Format::R64_UINT
+            | vk::Format::R64_SINT
+            | vk::Format::R64G64_UINT
+            | vk::Format::R64G64_SINT
+            | vk::Format::R64G64B64_UINT
+            | vk::Format::R64G64B64_SINT
+            | vk::Format::R64G64B64A64_UINT
+            | vk::Format::R64G64B64A64_SINT
+    )
+}
+
+/// Formats that can hold negative values, which filtering must not clamp to zero
+fn is_signed_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::R8_SNORM
+            | vk::Format::R8G8_SNORM
+            | vk::Format::R8G8B8_SNORM
+            | vk::Format::B8G8R8_SNORM
+            | vk::Format::R8G8B8A8_SNORM
+            | vk::Format::B8G8R8A8_SNORM
+            | vk::Format::A8B8G8R8_SNORM_PACK32
+            | vk::Format::A2R10G10B10_SNORM_PACK32
+            | vk::Format::A2B10G10R10_SNORM_PACK32
+            | vk::Format::R16_SNORM
+            | vk::Format::R16G16_SNORM
+            | vk::Format::R16G16B16_SNORM
+            | vk::Format::R16G16B16A16_SNORM
+            | vk::Format::R16_SFLOAT
+            | vk::Format::R16G16_SFLOAT
+            | vk::Format::R16G16B16_SFLOAT
+            | vk::Format::R16G16B16A16_SFLOAT
+            | vk::Format::R32_SFLOAT
+            | vk::Format::R32G32_SFLOAT
+            | vk::Format::R32G32B32_SFLOAT
+            | vk::Format::R32G32B32A32_SFLOAT
+            | vk::Format::R64_SFLOAT
+            | vk::Format::R64G64_SFLOAT
+            | vk::Format::R64G64B64_SFLOAT
+            | vk::Format::R64G64B64A64_SFLOAT
+    )
+}
@@ -0,0 +1,208 @@
+use crate::vulkan::core::Device;
+use anyhow::{Context as AnyhowContext, Result};
+use ash::{version::DeviceV1_0, vk};
+use derive_builder::Builder;
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
+/// How a sampler filters and addresses an image.
+/// Equal descriptions share one sampler through the device's sampler cache.
+#[derive(Builder, Clone, Copy, Debug)]
+#[builder(default)]
+pub struct SamplerDesc {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode_u: vk::SamplerAddressMode,
+    pub address_mode_v: vk::SamplerAddressMode,
+    pub address_mode_w: vk::SamplerAddressMode,
+
+    /// Clamped to the device's `max_sampler_anisotropy`. A value of 1 disables anisotropic filtering.
+    pub max_anisotropy: u32,
+
+    /// Makes this a comparison sampler, such as for shadow maps
+    pub compare_op: Option<vk::CompareOp>,
+
+    pub mip_lod_bias: f32,
+    pub min_lod: f32,
+
+    /// Unclamped by default, so that every mip level of an image can be sampled
+    pub max_lod: f32,
+
+    pub border_color: vk::BorderColor,
+}
+
+impl SamplerDesc {
+    /// Linear filtering that clamps coordinates to the edge, for render targets and lookup tables
+    pub fn clamp_to_edge() -> Self {
+        Self {
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            ..Default::default()
+        }
+    }
+
+    /// The create info for this description, with anisotropy clamped to the device limit
+    pub fn create_info(
+        &self,
+        max_sampler_anisotropy: f32,
+    ) -> vk::SamplerCreateInfoBuilder<'static> {
+        let max_anisotropy = (self.max_anisotropy as f32)
+            .min(max_sampler_anisotropy)
+            .max(1.0);
+        vk::SamplerCreateInfo::builder()
+            .mag_filter(self.mag_filter)
+            .min_filter(self.min_filter)
+            .address_mode_u(self.address_mode_u)
+            .address_mode_v(self.address_mode_v)
+            .address_mode_w(self.address_mode_w)
+            .anisotropy_enable(max_anisotropy > 1.0)
+            .max_anisotropy(max_anisotropy)
+            .border_color(self.border_color)
+            .unnormalized_coordinates(false)
+            .compare_enable(self.compare_op.is_some())
+            .compare_op(self.compare_op.unwrap_or(vk::CompareOp::ALWAYS))
+            .mipmap_mode(self.mipmap_mode)
+            .mip_lod_bias(self.mip_lod_bias)
+            .min_lod(self.min_lod)
+            .max_lod(self.max_lod)
+    }
+
+    // Floats are compared by their bits so that descriptions can be hashed
+    #[allow(clippy::type_complexity)]
+    fn key(
+        &self,
+    ) -> (
+        [vk::Filter; 2],
+        vk::SamplerMipmapMode,
+        [vk::SamplerAddressMode; 3],
+        u32,
+        Option<vk::CompareOp>,
+        [u32; 3],
+        vk::BorderColor,
+    ) {
+        (
+            [self.mag_filter, self.min_filter],
+            self.mipmap_mode,
+            [
+                self.address_mode_u,
+                self.address_mode_v,
+                self.address_mode_w,
+            ],
+            self.max_anisotropy,
+            self.compare_op,
+            [
+                self.mip_lod_bias.to_bits(),
+                self.min_lod.to_bits(),
+                self.max_lod.to_bits(),
+            ],
+            self.border_color,
+        )
+    }
+}
+
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            address_mode_w: vk::SamplerAddressMode::REPEAT,
+            max_anisotropy: 16,
+            compare_op: None,
+            mip_lod_bias: 0.0,
+            min_lod: 0.0,
+            max_lod: vk::LOD_CLAMP_NONE,
+            border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+        }
+    }
+}
+
+impl PartialEq for SamplerDesc {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for SamplerDesc {}
+
+impl Hash for SamplerDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// Samplers shared across a device, created the first time their description is asked for
+/// and destroyed along with the device
+#[derive(Default)]
+pub struct SamplerCache {
+    samplers: Mutex<HashMap<SamplerDesc, vk::Sampler>>,
+}
+
+impl SamplerCache {
+    pub fn get_or_create(
+        &self,
+        device: &ash::Device,
+        desc: &SamplerDesc,
+        max_sampler_anisotropy: f32,
+    ) -> Result<vk::Sampler> {
+        let mut samplers = self
+            .samplers
+            .lock()
+            .ok()
+            .context("Failed to lock the sampler cache!")?;
+        if let Some(sampler) = samplers.get(desc) {
+            return Ok(*sampler);
+        }
+        let create_info = desc.create_info(max_sampler_anisotropy);
+        let sampler = unsafe { device.create_sampler(&create_info, None) }?;
+        samplers.insert(*desc, sampler);
+        Ok(sampler)
+    }
+
+    /// Destroys every cached sampler, which must no longer be in use
+    pub fn destroy(&self, device: &ash::Device) {
+        if let Ok(mut samplers) = self.samplers.lock() {
+            for (_, sampler) in samplers.drain() {
+                unsafe { device.destroy_sampler(sampler, None) };
+            }
+        }
+    }
+}
+
+/// A sampler owned by its user rather than shared through the device's sampler cache
+pub struct Sampler {
+    pub handle: vk::Sampler,
+    device: Arc<Device>,
+}
+
+impl Sampler {
+    pub fn new(device: Arc<Device>, create_info: vk::SamplerCreateInfoBuilder) -> Result<Self> {
+        let handle = unsafe { device.handle.create_sampler(&create_info, None) }?;
+        let sampler = Self { handle, device };
+        Ok(sampler)
+    }
+
+    pub fn from_desc(device: Arc<Device>, desc: &SamplerDesc) -> Result<Self> {
+        let create_info = desc.create_info(device.max_sampler_anisotropy());
+        Self::new(device, create_info)
+    }
+
+    pub fn default(device: Arc<Device>) -> Result<Self> {
+        Self::from_desc(device, &SamplerDesc::default())
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        let handle = self.handle;
+        self.device
+            .defer_destruction(move |device| unsafe { device.destroy_sampler(handle, None) });
+    }
+}
@@ -0,0 +1,310 @@
+use crate::vulkan::core::{CpuToGpuBuffer, Device, Fence};
+use anyhow::{Context as AnyhowContext, Result};
+use ash::{version::DeviceV1_0, vk};
+use derive_builder::Builder;
+use log::debug;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use vk_mem::Allocator;
+
+#[derive(Builder, Clone, Debug)]
+pub struct StagingConfig {
+    /// Size of the persistently mapped ring that uploads are staged in.
+    /// Requests that do not fit are given a dedicated staging buffer.
+    #[builder(default = "StagingConfig::DEFAULT_RING_SIZE")]
+    pub ring_size: vk::DeviceSize,
+}
+
+impl StagingConfig {
+    pub const DEFAULT_RING_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+}
+
+impl Default for StagingConfig {
+    fn default() -> Self {
+        Self {
+            ring_size: Self::DEFAULT_RING_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StagedRegion {
+    pub buffer: vk::Buffer,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+}
+
+/// What the GPU has to finish before staged memory can be reused
+pub enum StagingRelease {
+    Fence(Arc<Fence>),
+    /// The frame value of the frame whose command buffers read the staged data
+    Frame(u64),
+}
+
+/// A persistently mapped staging buffer that is suballocated front to back.
+///
+/// Regions are recycled in the order they were allocated,
+/// once the fence or frame they were retired with has completed.
+pub struct StagingRing {
+    state: Mutex<RingState>,
+    next_lease: AtomicU64,
+    completed_frame: AtomicU64,
+    device: Arc<Device>,
+    allocator: Arc<Allocator>,
+}
+
+struct RingState {
+    buffer: CpuToGpuBuffer,
+    capacity: vk::DeviceSize,
+    head: vk::DeviceSize,
+    regions: VecDeque<RingRegion>,
+}
+
+struct RingRegion {
+    lease: u64,
+    start: vk::DeviceSize,
+    release: Option<StagingRelease>,
+}
+
+impl StagingRing {
+    pub fn new(
+        device: Arc<Device>,
+        allocator: Arc<Allocator>,
+        config: &StagingConfig,
+    ) -> Result<Self> {
+        let buffer = CpuToGpuBuffer::persistent_staging_buffer(
+            device.clone(),
+            allocator.clone(),
+            config.ring_size,
+        )?;
+        let state = RingState {
+            buffer,
+            capacity: config.ring_size,
+            head: 0,
+            regions: VecDeque::new(),
+        };
+        Ok(Self {
+            state: Mutex::new(state),
+            next_lease: AtomicU64::new(0),
+            completed_frame: AtomicU64::new(0),
+            device,
+            allocator,
+        })
+    }
+
+    /// Starts a group of allocations that are released together
+    pub fn lease(self: &Arc<Self>) -> StagingLease {
+        StagingLease {
+            id: self.next_lease.fetch_add(1, Ordering::Relaxed),
+            ring: self.clone(),
+            dedicated: Vec::new(),
+            retired: false,
+        }
+    }
+
+    /// Recycles every region whose frame or fence has completed
+    pub fn reclaim(&self, completed_frame: u64) -> Result<()> {
+        self.completed_frame
+            .fetch_max(completed_frame, Ordering::AcqRel);
+        let mut state = self.lock()?;
+        self.reclaim_locked(&mut state)
+    }
+
+    fn reclaim_locked(&self, state: &mut RingState) -> Result<()> {
+        let completed_frame = self.completed_frame.load(Ordering::Acquire);
+        while let Some(region) = state.regions.front() {
+            let complete = match region.release.as_ref() {
+                None => false,
+                Some(StagingRelease::Frame(frame_value)) => *frame_value <= completed_frame,
+                Some(StagingRelease::Fence(fence)) => {
+                    unsafe { self.device.handle.get_fence_status(fence.handle) }?
+                }
+            };
+            if !complete {
+                break;
+            }
+            state.regions.pop_front();
+        }
+        if state.regions.is_empty() {
+            state.head = 0;
+        }
+        Ok(())
+    }
+
+    fn write<T: Copy>(
+        &self,
+        lease: u64,
+        data: &[T],
+        alignment: vk::DeviceSize,
+    ) -> Result<Option<StagedRegion>> {
+        let size = (data.len() * std::mem::size_of::<T>()) as vk::DeviceSize;
+        let mut state = self.lock()?;
+        if size == 0 {
+            // Nothing is written, so there is no region to recycle
+            return Ok(Some(StagedRegion {
+                buffer: state.buffer.handle(),
+                offset: 0,
+                size,
+            }));
+        }
+        self.reclaim_locked(&mut state)?;
+
+        let offset = match state.find_space(size, alignment) {
+            Some(offset) => offset,
+            None => return Ok(None),
+        };
+        state.buffer.write_mapped(data, offset as _)?;
+        state.head = offset + size;
+        state.regions.push_back(RingRegion {
+            lease,
+            start: offset,
+            release: None,
+        });
+
+        Ok(Some(StagedRegion {
+            buffer: state.buffer.handle(),
+            offset,
+            size,
+        }))
+    }
+
+    fn retire(&self, lease: u64, release: impl Fn() -> StagingRelease) -> Result<()> {
+        let mut state = self.lock()?;
+        state
+            .regions
+            .iter_mut()
+            .filter(|region| region.lease == lease)
+            .for_each(|region| region.release = Some(release()));
+        Ok(())
+    }
+
+    fn discard(&self, lease: u64) -> Result<()> {
+        let mut state = self.lock()?;
+        state.regions.retain(|region| region.lease != lease);
+        if state.regions.is_empty() {
+            state.head = 0;
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<RingState>> {
+        self.state
+            .lock()
+            .ok()
+            .context("Failed to lock the staging ring!")
+    }
+}
+
+impl RingState {
+    fn find_space(
+        &self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+    ) -> Option<vk::DeviceSize> {
+        let head = align(self.head, alignment);
+        let tail = match self.regions.front() {
+            Some(region) => region.start,
+            None => return Some(0).filter(|_| size <= self.capacity),
+        };
+
+        // The head only meets the tail when the ring is full
+        if self.head > tail {
+            if head + size <= self.capacity {
+                Some(head)
+            } else if size <= tail {
+                Some(0)
+            } else {
+                None
+            }
+        } else if head + size <= tail {
+            Some(head)
+        } else {
+            None
+        }
+    }
+}
+
+/// Staging allocations made by a single upload.
+///
+/// Allocations come from the ring when there is room,
+/// and from dedicated buffers owned by the lease otherwise.
+/// A lease that is dropped without being retired returns its regions immediately.
+pub struct StagingLease {
+    id: u64,
+    ring: Arc<StagingRing>,
+    dedicated: Vec<CpuToGpuBuffer>,
+    retired: bool,
+}
+
+impl StagingLease {
+    /// Copies the data into staging memory for buffer to buffer copies,
+    /// which have no alignment requirements, and returns where it was placed
+    pub fn push<T: Copy>(&mut self, data: &[T]) -> Result<StagedRegion> {
+        self.push_aligned(data, 1)
+    }
+
+    /// Copies the data into staging memory at an offset that is a multiple of the alignment
+    pub fn push_aligned<T: Copy>(
+        &mut self,
+        data: &[T],
+        alignment: vk::DeviceSize,
+    ) -> Result<StagedRegion> {
+        if let Some(region) = self.ring.write(self.id, data, alignment)? {
+            return Ok(region);
+        }
+
+        let size = (data.len() * std::mem::size_of::<T>()) as vk::DeviceSize;
+        debug!(
+            "Staging ring cannot fit {} bytes, using a dedicated staging buffer",
+            size
+        );
+        let buffer = CpuToGpuBuffer::staging_buffer(
+            self.ring.device.clone(),
+            self.ring.allocator.clone(),
+            size,
+        )?;
+        buffer.upload_data(data, 0)?;
+        buffer.flush(0, size as _);
+        let region = StagedRegion {
+            buffer: buffer.handle(),
+            offset: 0,
+            size,
+        };
+        self.dedicated.push(buffer);
+        Ok(region)
+    }
+
+    /// Hands the ring regions back once the GPU is done reading them.
+    /// Dedicated buffers stay alive for as long as the lease does.
+    pub fn retire(&mut self, release: StagingRelease) -> Result<()> {
+        self.retired = true;
+        match release {
+            StagingRelease::Fence(fence) => self
+                .ring
+                .retire(self.id, || StagingRelease::Fence(fence.clone())),
+            StagingRelease::Frame(frame_value) => self
+                .ring
+                .retire(self.id, || StagingRelease::Frame(frame_value)),
+        }
+    }
+}
+
+impl Drop for StagingLease {
+    fn drop(&mut self) {
+        if self.retired {
+            return;
+        }
+        if let Err(error) = self.ring.discard(self.id) {
+            log::error!("Failed to discard staging allocations: {}", error);
+        }
+    }
+}
+
+fn align(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    (value + alignment - 1) / alignment * alignment
+}
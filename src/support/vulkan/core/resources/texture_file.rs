@@ -0,0 +1,521 @@
+use crate::vulkan::core::{
+    format_copy_alignment, format_image_size, ColorSpace, ImageDescription, MipmapFilter,
+};
+use anyhow::{bail, ensure, Context as AnyhowContext, Result};
+use ash::vk;
+use std::{convert::TryFrom, path::Path};
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const KTX2_LEVEL_INDEX_OFFSET: usize = 80;
+
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+const DDS_HEADER_SIZE: u32 = 124;
+const DDS_DATA_OFFSET: usize = 128;
+const DDS_DX10_DATA_OFFSET: usize = 148;
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x200000;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+const DDS_DIMENSION_TEXTURE3D: u32 = 4;
+
+impl ImageDescription {
+    /// Loads a KTX2 file, keeping its mip levels, array layers, cube faces and volume slices.
+    /// Supercompressed files are not supported.
+    pub fn from_ktx2<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        Self::parse_ktx2(&bytes).with_context(|| format!("path: {}", path.display()))
+    }
+
+    /// Loads a DDS file, keeping its mip levels, array layers and cube faces.
+    /// Both legacy headers and DX10 headers are supported.
+    pub fn from_dds<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        Self::parse_dds(&bytes).with_context(|| format!("path: {}", path.display()))
+    }
+
+    /// Loads a DDS file like `from_dds`, picking the format of legacy files with the color space.
+    /// Their formats do not say whether the pixels are sRGB encoded, while DX10 formats do and are kept.
+    pub fn from_dds_with_color_space<P: AsRef<Path>>(
+        path: P,
+        color_space: ColorSpace,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        let mut description =
+            Self::parse_dds(&bytes).with_context(|| format!("path: {}", path.display()))?;
+        if !has_dx10_header(&bytes) {
+            description.set_color_space(color_space);
+        }
+        Ok(description)
+    }
+
+    /// Writes the mip levels and array layers to a KTX2 file.
+    /// Only uncompressed formats can be written.
+    pub fn save_ktx2<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write_ktx2(path.as_ref(), 1)
+    }
+
+    /// Writes the mip levels to a KTX2 file as a cubemap, with every six layers forming the faces of a cube
+    pub fn save_ktx2_cubemap<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        ensure!(
+            self.array_layers % 6 == 0,
+            "Cubemaps need six faces, but the image description has {} layers!",
+            self.array_layers
+        );
+        self.write_ktx2(path.as_ref(), 6)
+    }
+
+    /// The size in bytes of one mip level, including every layer or volume slice
+    pub fn level_size(&self, level: u32) -> Result<usize> {
+        let (width, height) = self.mip_extent(level);
+        let slices = self.mip_depth(level) as usize * self.array_layers as usize;
+        format_image_size(self.format, width, height)?
+            .checked_mul(slices)
+            .context("Image level size overflows!")
+    }
+
+    /// Where each mip level starts when they are packed the way precomputed mips are,
+    /// along with the total size in bytes
+    pub fn packed_level_offsets(&self) -> Result<(Vec<usize>, usize)> {
+        let alignment = format_copy_alignment(self.format)?;
+        let mut size = 0;
+        let mut offsets = Vec::with_capacity(self.mip_levels as usize);
+        for level in 0..self.mip_levels {
+            let offset = align_level(size, alignment);
+            offsets.push(offset);
+            size = offset + self.level_size(level)?;
+        }
+        Ok((offsets, size))
+    }
+
+    fn write_ktx2(&self, path: &Path, face_count: u32) -> Result<()> {
+        let (type_size, data_format_descriptor) = ktx2_data_format_descriptor(self.format)?;
+
+        // Without precomputed mips only the base level is written,
+        // and a level count of zero asks readers to generate the rest
+        let (levels, level_count) = if self.has_precomputed_mips() {
+            let levels = self
+                .mip_offsets
+                .iter()
+                .enumerate()
+                .map(|(level, offset)| {
+                    let size = self.level_size(level as _)?;
+                    self.pixels
+                        .get(*offset..*offset + size)
+                        .context("Image description is smaller than its mip levels describe!")
+                })
+                .collect::<Result<Vec<_>>>()?;
+            (levels, self.mip_levels)
+        } else {
+            let size = self.level_size(0)?;
+            let base = self
+                .pixels
+                .get(..size)
+                .context("Image description is smaller than its base level!")?;
+            (vec![base], if self.mip_levels > 1 { 0 } else { 1 })
+        };
+        let layer_count = match self.array_layers / face_count {
+            1 => 0,
+            layers => layers,
+        };
+
+        // Levels are stored from the smallest to the largest, after the data format descriptor.
+        // KTX2 aligns them the same way buffer to image copies need them aligned.
+        let alignment = format_copy_alignment(self.format)?;
+        let descriptor_offset = KTX2_LEVEL_INDEX_OFFSET + levels.len() * 24;
+        let mut level_offsets = vec![0; levels.len()];
+        let mut end = descriptor_offset + data_format_descriptor.len();
+        for (level, data) in levels.iter().enumerate().rev() {
+            level_offsets[level] = align_level(end, alignment);
+            end = level_offsets[level] + data.len();
+        }
+
+        let mut bytes = Vec::with_capacity(end);
+        bytes.extend_from_slice(&KTX2_IDENTIFIER);
+        let header = [
+            self.format.as_raw() as u32,
+            type_size,
+            self.width,
+            self.height,
+            if self.is_volume() { self.depth } else { 0 },
+            layer_count,
+            face_count,
+            level_count,
+            0,
+            descriptor_offset as u32,
+            data_format_descriptor.len() as u32,
+            0,
+            0,
+        ];
+        header
+            .iter()
+            .for_each(|field| bytes.extend_from_slice(&field.to_le_bytes()));
+        // No supercompression global data
+        bytes.extend_from_slice(&[0; 16]);
+        for (offset, data) in level_offsets.iter().zip(levels.iter()) {
+            let length = data.len() as u64;
+            bytes.extend_from_slice(&(*offset as u64).to_le_bytes());
+            bytes.extend_from_slice(&length.to_le_bytes());
+            bytes.extend_from_slice(&length.to_le_bytes());
+        }
+        bytes.extend_from_slice(&data_format_descriptor);
+        for (offset, data) in level_offsets.iter().zip(levels.iter()).rev() {
+            bytes.resize(*offset, 0);
+            bytes.extend_from_slice(data);
+        }
+
+        std::fs::write(path, bytes).with_context(|| format!("path: {}", path.display()))
+    }
+
+    fn parse_ktx2(bytes: &[u8]) -> Result<Self> {
+        ensure!(
+            bytes.len() >= KTX2_LEVEL_INDEX_OFFSET && bytes[..12] == KTX2_IDENTIFIER,
+            "File is not a KTX2 file!"
+        );
+        let vk_format = read_u32(bytes, 12)?;
+        let width = read_u32(bytes, 20)?;
+        let height = read_u32(bytes, 24)?.max(1);
+        let depth = read_u32(bytes, 28)?;
+        let layer_count = read_u32(bytes, 32)?.max(1);
+        let face_count = read_u32(bytes, 36)?;
+        let level_count = read_u32(bytes, 40)?;
+        let supercompression_scheme = read_u32(bytes, 44)?;
+
+        let format = vk::Format::from_raw(vk_format as _);
+        ensure!(
+            format != vk::Format::UNDEFINED,
+            "KTX2 files without a Vulkan format, such as Basis Universal, are not supported!"
+        );
+        ensure!(
+            supercompression_scheme == 0,
+            "Supercompressed KTX2 files are not supported!"
+        );
+        ensure!(
+            face_count == 1 || face_count == 6,
+            "KTX2 file has {} faces!",
+            face_count
+        );
+        let array_layers = layer_count
+            .checked_mul(face_count)
+            .context("KTX2 file has too many layers!")?;
+        ensure!(
+            depth <= 1 || array_layers == 1,
+            "Arrays of volume textures are not supported!"
+        );
+
+        // A level count of zero asks for the mips to be generated from the base level
+        let stored_levels = level_count.max(1);
+        let levels = (0..stored_levels as usize)
+            .map(|level| {
+                let entry = KTX2_LEVEL_INDEX_OFFSET + level * 24;
+                let offset = read_u64(bytes, entry)?;
+                let length = read_u64(bytes, entry + 8)?;
+                let end = offset
+                    .checked_add(length)
+                    .context("KTX2 level data is out of bounds!")?;
+                bytes
+                    .get(usize::try_from(offset)?..usize::try_from(end)?)
+                    .context("KTX2 level data is out of bounds!")
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let (pixels, mip_offsets) = pack_levels(&levels, format_copy_alignment(format)?);
+        let mut description = Self {
+            format,
+            width,
+            height,
+            depth: depth.max(1),
+            pixels,
+            mip_levels: level_count,
+            array_layers,
+            mip_offsets,
+            mip_filter: MipmapFilter::default(),
+            color_space: ColorSpace::of_format(format),
+        };
+        if level_count == 0 {
+            description.mip_levels = Self::calculate_mip_levels(width, height);
+            description.mip_offsets.clear();
+        }
+        Ok(description)
+    }
+
+    fn parse_dds(bytes: &[u8]) -> Result<Self> {
+        ensure!(
+            bytes.len() >= DDS_DATA_OFFSET && &bytes[..4] == DDS_MAGIC,
+            "File is not a DDS file!"
+        );
+        ensure!(
+            read_u32(bytes, 4)? == DDS_HEADER_SIZE,
+            "DDS header has an unexpected size!"
+        );
+        let flags = read_u32(bytes, 8)?;
+        let height = read_u32(bytes, 12)?;
+        let width = read_u32(bytes, 16)?;
+        let mip_map_count = read_u32(bytes, 28)?;
+        let pixel_format_flags = read_u32(bytes, 80)?;
+        let caps2 = read_u32(bytes, 112)?;
+
+        ensure!(
+            caps2 & DDSCAPS2_VOLUME == 0,
+            "Volume textures are not supported!"
+        );
+        let mip_levels = if flags & DDSD_MIPMAPCOUNT != 0 {
+            mip_map_count.max(1)
+        } else {
+            1
+        };
+
+        let (format, array_layers, data_offset) = if has_dx10_header(bytes) {
+            ensure!(
+                bytes.len() >= DDS_DX10_DATA_OFFSET,
+                "DDS file is missing its DX10 header!"
+            );
+            let dxgi_format = read_u32(bytes, 128)?;
+            let resource_dimension = read_u32(bytes, 132)?;
+            let misc_flag = read_u32(bytes, 136)?;
+            let array_size = read_u32(bytes, 140)?.max(1);
+            ensure!(
+                resource_dimension != DDS_DIMENSION_TEXTURE3D,
+                "Volume textures are not supported!"
+            );
+            let faces = if misc_flag & DDS_RESOURCE_MISC_TEXTURECUBE != 0 {
+                6
+            } else {
+                1
+            };
+            (
+                dxgi_to_vulkan_format(dxgi_format)?,
+                array_size
+                    .checked_mul(faces)
+                    .context("DDS file has too many layers!")?,
+                DDS_DX10_DATA_OFFSET,
+            )
+        } else {
+            let layers = if caps2 & DDSCAPS2_CUBEMAP != 0 { 6 } else { 1 };
+            (
+                legacy_dds_format(bytes, pixel_format_flags)?,
+                layers,
+                DDS_DATA_OFFSET,
+            )
+        };
+
+        let level_sizes = (0..mip_levels)
+            .map(|level| {
+                let level_width = (width >> level).max(1);
+                let level_height = (height >> level).max(1);
+                format_image_size(format, level_width, level_height)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // DDS stores each layer with its whole mip chain, while uploads expect
+        // each level to hold all of the layers, so the data is regrouped by level
+        let layer_size = level_sizes
+            .iter()
+            .try_fold(0_usize, |total, size| total.checked_add(*size))
+            .context("DDS layer size overflows!")?;
+        let data_end = layer_size
+            .checked_mul(array_layers as usize)
+            .and_then(|data_size| data_size.checked_add(data_offset))
+            .context("DDS file is smaller than its header describes!")?;
+        let data = bytes
+            .get(data_offset..data_end)
+            .context("DDS file is smaller than its header describes!")?;
+        let levels = level_sizes
+            .iter()
+            .enumerate()
+            .map(|(level, size)| {
+                let level_offset = level_sizes[..level].iter().sum::<usize>();
+                (0..array_layers as usize)
+                    .flat_map(|layer| {
+                        let start = layer * layer_size + level_offset;
+                        data[start..start + size].iter().copied()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let levels = levels
+            .iter()
+            .map(|level| level.as_slice())
+            .collect::<Vec<_>>();
+
+        let (pixels, mip_offsets) = pack_levels(&levels, format_copy_alignment(format)?);
+        Ok(Self {
+            format,
+            width,
+            height,
+            depth: 1,
+            pixels,
+            mip_levels,
+            array_layers,
+            mip_offsets,
+            mip_filter: MipmapFilter::default(),
+            color_space: ColorSpace::of_format(format),
+        })
+    }
+}
+
+/// Concatenates mip levels into one buffer, returning where each level starts.
+/// Levels start at offsets that buffer to image copies of the format accept.
+fn pack_levels(levels: &[&[u8]], alignment: usize) -> (Vec<u8>, Vec<usize>) {
+    let mut pixels = Vec::new();
+    let mut offsets = Vec::with_capacity(levels.len());
+    for level in levels {
+        let offset = align_level(pixels.len(), alignment);
+        pixels.resize(offset, 0);
+        pixels.extend_from_slice(level);
+        offsets.push(offset);
+    }
+    (pixels, offsets)
+}
+
+fn align_level(offset: usize, alignment: usize) -> usize {
+    (offset + alignment - 1) / alignment * alignment
+}
+
+/// A basic data format descriptor for an uncompressed format, along with the size of its channels
+fn ktx2_data_format_descriptor(format: vk::Format) -> Result<(u32, Vec<u8>)> {
+    let (channels, channel_size, float, srgb) = match format {
+        vk::Format::R8_UNORM => (1, 1, false, false),
+        vk::Format::R8_SRGB => (1, 1, false, true),
+        vk::Format::R8G8_UNORM => (2, 1, false, false),
+        vk::Format::R8G8B8A8_UNORM => (4, 1, false, false),
+        vk::Format::R8G8B8A8_SRGB => (4, 1, false, true),
+        vk::Format::R16G16B16A16_UNORM => (4, 2, false, false),
+        vk::Format::R16_SFLOAT => (1, 2, true, false),
+        vk::Format::R16G16_SFLOAT => (2, 2, true, false),
+        vk::Format::R16G16B16A16_SFLOAT => (4, 2, true, false),
+        vk::Format::R32_SFLOAT => (1, 4, true, false),
+        vk::Format::R32G32_SFLOAT => (2, 4, true, false),
+        vk::Format::R32G32B32A32_SFLOAT => (4, 4, true, false),
+        _ => bail!("Writing format {:?} to KTX2 is not supported!", format),
+    };
+
+    let block_size: u32 = 24 + 16 * channels;
+    let mut block = Vec::with_capacity(block_size as usize);
+    // Khronos vendor, basic descriptor type, version 2
+    block.extend_from_slice(&0u32.to_le_bytes());
+    block.extend_from_slice(&(2 | block_size << 16).to_le_bytes());
+    // RGBSDA color model, BT.709 primaries, linear or sRGB transfer, straight alpha
+    block.extend_from_slice(&[1, 1, if srgb { 2 } else { 1 }, 0]);
+    // One texel per block, in a single plane
+    block.extend_from_slice(&[0; 4]);
+    block.extend_from_slice(&[(channels * channel_size) as u8, 0, 0, 0, 0, 0, 0, 0]);
+    for channel in 0..channels {
+        let bit_length = channel_size * 8;
+        // Alpha is channel 15 in the RGBSDA model, and is never sRGB encoded
+        let channel_id = if channel == 3 { 15 } else { channel };
+        let qualifiers = match (float, srgb && channel == 3) {
+            (true, _) => 0xC0,
+            (false, true) => 0x10,
+            (false, false) => 0,
+        };
+        let (lower, upper) = if float {
+            ((-1.0f32).to_bits(), 1.0f32.to_bits())
+        } else {
+            (0, u32::MAX >> (32 - bit_length))
+        };
+        block.extend_from_slice(&((channel * bit_length) as u16).to_le_bytes());
+        block.push((bit_length - 1) as u8);
+        block.push((channel_id | qualifiers) as u8);
+        block.extend_from_slice(&[0; 4]);
+        block.extend_from_slice(&lower.to_le_bytes());
+        block.extend_from_slice(&upper.to_le_bytes());
+    }
+
+    let mut descriptor = (block.len() as u32 + 4).to_le_bytes().to_vec();
+    descriptor.extend(block);
+    Ok((channel_size, descriptor))
+}
+
+fn legacy_dds_format(bytes: &[u8], pixel_format_flags: u32) -> Result<vk::Format> {
+    if pixel_format_flags & DDPF_FOURCC != 0 {
+        let format = match &bytes[84..88] {
+            b"DXT1" => vk::Format::BC1_RGBA_UNORM_BLOCK,
+            b"DXT2" | b"DXT3" => vk::Format::BC2_UNORM_BLOCK,
+            b"DXT4" | b"DXT5" => vk::Format::BC3_UNORM_BLOCK,
+            b"ATI1" | b"BC4U" => vk::Format::BC4_UNORM_BLOCK,
+            b"BC4S" => vk::Format::BC4_SNORM_BLOCK,
+            b"ATI2" | b"BC5U" => vk::Format::BC5_UNORM_BLOCK,
+            b"BC5S" => vk::Format::BC5_SNORM_BLOCK,
+            // Direct3D 9 format codes stored in place of a four character code
+            [36, 0, 0, 0] => vk::Format::R16G16B16A16_UNORM,
+            [113, 0, 0, 0] => vk::Format::R16G16B16A16_SFLOAT,
+            [116, 0, 0, 0] => vk::Format::R32G32B32A32_SFLOAT,
+            four_cc => bail!(
+                "DDS four character code {:?} is not supported!",
+                String::from_utf8_lossy(four_cc)
+            ),
+        };
+        return Ok(format);
+    }
+
+    let bit_count = read_u32(bytes, 88)?;
+    let red_mask = read_u32(bytes, 92)?;
+    ensure!(
+        pixel_format_flags & DDPF_RGB != 0 && bit_count == 32,
+        "Only 32-bit uncompressed DDS files are supported!"
+    );
+    match red_mask {
+        0x0000_00ff => Ok(vk::Format::R8G8B8A8_UNORM),
+        0x00ff_0000 => Ok(vk::Format::B8G8R8A8_UNORM),
+        _ => bail!("DDS channel masks are not supported!"),
+    }
+}
+
+fn dxgi_to_vulkan_format(dxgi_format: u32) -> Result<vk::Format> {
+    let format = match dxgi_format {
+        2 => vk::Format::R32G32B32A32_SFLOAT,
+        10 => vk::Format::R16G16B16A16_SFLOAT,
+        11 => vk::Format::R16G16B16A16_UNORM,
+        24 => vk::Format::A2B10G10R10_UNORM_PACK32,
+        28 => vk::Format::R8G8B8A8_UNORM,
+        29 => vk::Format::R8G8B8A8_SRGB,
+        34 => vk::Format::R16G16_SFLOAT,
+        41 => vk::Format::R32_SFLOAT,
+        49 => vk::Format::R8G8_UNORM,
+        54 => vk::Format::R16_SFLOAT,
+        61 => vk::Format::R8_UNORM,
+        71 => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        72 => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        74 => vk::Format::BC2_UNORM_BLOCK,
+        75 => vk::Format::BC2_SRGB_BLOCK,
+        77 => vk::Format::BC3_UNORM_BLOCK,
+        78 => vk::Format::BC3_SRGB_BLOCK,
+        80 => vk::Format::BC4_UNORM_BLOCK,
+        81 => vk::Format::BC4_SNORM_BLOCK,
+        83 => vk::Format::BC5_UNORM_BLOCK,
+        84 => vk::Format::BC5_SNORM_BLOCK,
+        87 => vk::Format::B8G8R8A8_UNORM,
+        91 => vk::Format::B8G8R8A8_SRGB,
+        95 => vk::Format::BC6H_UFLOAT_BLOCK,
+        96 => vk::Format::BC6H_SFLOAT_BLOCK,
+        98 => vk::Format::BC7_UNORM_BLOCK,
+        99 => vk::Format::BC7_SRGB_BLOCK,
+        _ => bail!("DXGI format {} is not supported!", dxgi_format),
+    };
+    Ok(format)
+}
+
+fn has_dx10_header(bytes: &[u8]) -> bool {
+    let pixel_format_flags = read_u32(bytes, 80).unwrap_or_default();
+    pixel_format_flags & DDPF_FOURCC != 0 && bytes.get(84..88) == Some(&b"DX10"[..])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    let field = bytes
+        .get(offset..offset + 4)
+        .context("Texture file header is truncated!")?;
+    Ok(u32::from_le_bytes([field[0], field[1], field[2], field[3]]))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64> {
+    let low = read_u32(bytes, offset)? as u64;
+    let high = read_u32(bytes, offset + 4)? as u64;
+    Ok(high << 32 | low)
+}
@@ -0,0 +1,454 @@
+use crate::vulkan::core::{
+    format_copy_alignment, image_layout_barrier, BlitImage, BufferToBufferCopy, BufferToImageCopy,
+    CommandPool, Context, Device, Fence, ImageLayoutTransition, PipelineBarrier,
+    PipelineBarrierBuilder, Semaphore, StagedRegion, StagingLease, StagingRelease,
+};
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use ash::{version::DeviceV1_0, vk};
+use std::sync::Arc;
+
+/// Records many copies, layout transitions and blits into as few command buffers as possible.
+///
+/// Source data is written into the context's staging ring as it is recorded,
+/// and everything is submitted at once with a single fence.
+///
+/// An asynchronous batch records copies on the dedicated transfer queue when there is one.
+/// Work that needs the graphics queue, such as mipmap blits, is recorded separately
+/// and runs once the graphics queue has acquired ownership of the uploaded resources.
+pub struct UploadBatch {
+    transfer: BatchCommands,
+    graphics: Option<BatchCommands>,
+    staging: StagingLease,
+    retained: Vec<Box<dyn Send>>,
+    device: Arc<Device>,
+}
+
+impl UploadBatch {
+    /// Records everything on the graphics queue
+    pub fn new(context: &Context) -> Result<Self> {
+        let transfer = BatchCommands::new(
+            context,
+            context.graphics_queue(),
+            context.physical_device.graphics_queue_family_index,
+        )?;
+        Ok(Self {
+            transfer,
+            graphics: None,
+            staging: context.staging.lease(),
+            retained: Vec::new(),
+            device: context.device.clone(),
+        })
+    }
+
+    /// Records copies on the dedicated transfer queue,
+    /// falling back to the graphics queue if the device does not have one
+    pub fn asynchronous(context: &Context) -> Result<Self> {
+        let (queue, queue_family_index) = match (
+            context.transfer_queue(),
+            context.physical_device.transfer_queue_family_index,
+        ) {
+            (Some(queue), Some(queue_family_index)) => (queue, queue_family_index),
+            _ => return Self::new(context),
+        };
+        let transfer = BatchCommands::new(context, queue, queue_family_index)?;
+        let graphics = BatchCommands::new(
+            context,
+            context.graphics_queue(),
+            context.physical_device.graphics_queue_family_index,
+        )?;
+        Ok(Self {
+            transfer,
+            graphics: Some(graphics),
+            staging: context.staging.lease(),
+            retained: Vec::new(),
+            device: context.device.clone(),
+        })
+    }
+
+    pub fn uses_transfer_queue(&self) -> bool {
+        self.graphics.is_some()
+    }
+
+    pub fn transfer_command_buffer(&self) -> vk::CommandBuffer {
+        self.transfer.command_buffer
+    }
+
+    /// Commands recorded here run after ownership of the uploaded resources has been acquired
+    pub fn graphics_command_buffer(&self) -> vk::CommandBuffer {
+        self.graphics
+            .as_ref()
+            .unwrap_or(&self.transfer)
+            .command_buffer
+    }
+
+    /// Copies the data into the staging ring and returns where it was placed
+    pub fn stage<T: Copy>(&mut self, data: &[T]) -> Result<StagedRegion> {
+        self.staging.push(data)
+    }
+
+    /// Copies pixels into the staging ring at an offset that buffer to image copies of the format accept
+    pub fn stage_pixels(&mut self, pixels: &[u8], format: vk::Format) -> Result<StagedRegion> {
+        let alignment = format_copy_alignment(format)?;
+        self.staging.push_aligned(pixels, alignment as _)
+    }
+
+    /// Keeps a resource that recorded commands use, such as an image view, alive until the upload finishes
+    pub fn retain(&mut self, resource: impl Send + 'static) {
+        self.retained.push(Box::new(resource));
+    }
+
+    pub fn copy_buffer_to_buffer(&self, info: &BufferToBufferCopy) {
+        self.device
+            .copy_buffer_to_buffer(self.transfer_command_buffer(), info);
+    }
+
+    pub fn copy_buffer_to_image(&self, info: &BufferToImageCopy) {
+        self.device
+            .copy_buffer_to_image(self.transfer_command_buffer(), info);
+    }
+
+    pub fn pipeline_barrier(&self, info: &PipelineBarrier) {
+        self.device
+            .pipeline_barrier(self.transfer_command_buffer(), info);
+    }
+
+    pub fn transition_image(&self, image: vk::Image, info: &ImageLayoutTransition) -> Result<()> {
+        self.pipeline_barrier(&image_layout_barrier(image, info)?);
+        Ok(())
+    }
+
+    pub fn graphics_pipeline_barrier(&self, info: &PipelineBarrier) {
+        self.device
+            .pipeline_barrier(self.graphics_command_buffer(), info);
+    }
+
+    pub fn graphics_transition_image(
+        &self,
+        image: vk::Image,
+        info: &ImageLayoutTransition,
+    ) -> Result<()> {
+        self.graphics_pipeline_barrier(&image_layout_barrier(image, info)?);
+        Ok(())
+    }
+
+    pub fn blit_image(&self, info: &BlitImage) {
+        self.device.blit_image(self.graphics_command_buffer(), info);
+    }
+
+    /// Makes the transfer writes to the range of the buffer visible to the graphics queue,
+    /// releasing and acquiring ownership of the range when the copy ran on the transfer queue
+    pub fn transfer_buffer_ownership(
+        &self,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    ) -> Result<()> {
+        let barrier = |src_access_mask, dst_access_mask, (src_family, dst_family)| {
+            vk::BufferMemoryBarrier::builder()
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .src_queue_family_index(src_family)
+                .dst_queue_family_index(dst_family)
+                .buffer(buffer)
+                .offset(offset)
+                .size(size)
+                .build()
+        };
+        self.transfer_ownership(|src_access_mask, dst_access_mask, families| {
+            let mut builder = PipelineBarrierBuilder::default();
+            builder
+                .buffer_memory_barriers(vec![barrier(src_access_mask, dst_access_mask, families)])
+                .image_memory_barriers(Vec::new());
+            builder
+        })
+    }
+
+    /// Makes the transfer writes to the image visible to the graphics queue without changing its layout,
+    /// releasing and acquiring ownership when the copy ran on the transfer queue
+    pub fn transfer_image_ownership(
+        &self,
+        image: vk::Image,
+        subresource_range: vk::ImageSubresourceRange,
+        layout: vk::ImageLayout,
+    ) -> Result<()> {
+        let barrier = |src_access_mask, dst_access_mask, (src_family, dst_family)| {
+            vk::ImageMemoryBarrier::builder()
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .src_queue_family_index(src_family)
+                .dst_queue_family_index(dst_family)
+                .old_layout(layout)
+                .new_layout(layout)
+                .image(image)
+                .subresource_range(subresource_range)
+                .build()
+        };
+        self.transfer_ownership(|src_access_mask, dst_access_mask, families| {
+            let mut builder = PipelineBarrierBuilder::default();
+            builder.image_memory_barriers(vec![barrier(
+                src_access_mask,
+                dst_access_mask,
+                families,
+            )]);
+            builder
+        })
+    }
+
+    fn transfer_ownership(
+        &self,
+        barrier: impl Fn(vk::AccessFlags, vk::AccessFlags, (u32, u32)) -> PipelineBarrierBuilder,
+    ) -> Result<()> {
+        let build = |mut builder: PipelineBarrierBuilder, src_stage_mask, dst_stage_mask| {
+            builder
+                .src_stage_mask(src_stage_mask)
+                .dst_stage_mask(dst_stage_mask)
+                .build()
+                .map_err(|error| anyhow!("{}", error))
+        };
+
+        match self.graphics.as_ref() {
+            Some(graphics) => {
+                let families = (
+                    self.transfer.queue_family_index,
+                    graphics.queue_family_index,
+                );
+                let release = build(
+                    barrier(
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::empty(),
+                        families,
+                    ),
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                )?;
+                let acquire = build(
+                    barrier(
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+                        families,
+                    ),
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                )?;
+                self.device
+                    .pipeline_barrier(self.transfer.command_buffer, &release);
+                self.device
+                    .pipeline_barrier(graphics.command_buffer, &acquire);
+            }
+            None => {
+                let families = (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED);
+                let visibility = build(
+                    barrier(
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+                        families,
+                    ),
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                )?;
+                self.device
+                    .pipeline_barrier(self.transfer.command_buffer, &visibility);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn submit(mut self) -> Result<PendingUpload> {
+        let fence = Arc::new(Fence::new(
+            self.device.clone(),
+            vk::FenceCreateFlags::empty(),
+        )?);
+        let mut semaphore = None;
+
+        self.transfer.end(&self.device)?;
+        match self.graphics.as_ref() {
+            Some(graphics) => {
+                graphics.end(&self.device)?;
+                let ownership_transferred = Semaphore::new(self.device.clone())?;
+                let transfer_fence = Arc::new(Fence::new(
+                    self.device.clone(),
+                    vk::FenceCreateFlags::empty(),
+                )?);
+                self.transfer.submit(
+                    &self.device,
+                    &[],
+                    &[ownership_transferred.handle],
+                    transfer_fence.handle,
+                )?;
+
+                // Only the transfer queue reads staged data, so it is retired
+                // before the graphics submission, which could still fail
+                self.staging
+                    .retire(StagingRelease::Fence(transfer_fence.clone()))?;
+                let submitted = graphics.submit(
+                    &self.device,
+                    &[ownership_transferred.handle],
+                    &[],
+                    fence.handle,
+                );
+                if let Err(error) = submitted {
+                    // The semaphore, command buffers and retained resources
+                    // cannot be released while the transfer is still running
+                    unsafe {
+                        self.device.handle.wait_for_fences(
+                            &[transfer_fence.handle],
+                            true,
+                            std::u64::MAX,
+                        )
+                    }?;
+                    return Err(error);
+                }
+                semaphore = Some(ownership_transferred);
+            }
+            None => {
+                self.transfer.submit(&self.device, &[], &[], fence.handle)?;
+                self.staging.retire(StagingRelease::Fence(fence.clone()))?;
+            }
+        }
+
+        let mut commands = vec![self.transfer];
+        commands.extend(self.graphics);
+
+        Ok(PendingUpload {
+            fence,
+            _semaphore: semaphore,
+            _commands: commands,
+            _staging: self.staging,
+            _retained: self.retained,
+            device: self.device,
+        })
+    }
+}
+
+struct BatchCommands {
+    command_buffer: vk::CommandBuffer,
+    queue: vk::Queue,
+    queue_family_index: u32,
+    _command_pool: CommandPool,
+}
+
+impl BatchCommands {
+    fn new(context: &Context, queue: vk::Queue, queue_family_index: u32) -> Result<Self> {
+        let command_pool = CommandPool::new(
+            context.device.clone(),
+            queue,
+            vk::CommandPoolCreateInfo::builder()
+                .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+                .queue_family_index(queue_family_index),
+        )?;
+        let command_buffer =
+            command_pool.allocate_command_buffers(1, vk::CommandBufferLevel::PRIMARY)?[0];
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        unsafe {
+            context
+                .device
+                .handle
+                .begin_command_buffer(command_buffer, &begin_info)
+        }?;
+        Ok(Self {
+            command_buffer,
+            queue,
+            queue_family_index,
+            _command_pool: command_pool,
+        })
+    }
+
+    fn end(&self, device: &Device) -> Result<()> {
+        unsafe { device.handle.end_command_buffer(self.command_buffer) }?;
+        Ok(())
+    }
+
+    fn submit(
+        &self,
+        device: &Device,
+        wait_semaphores: &[vk::Semaphore],
+        signal_semaphores: &[vk::Semaphore],
+        fence: vk::Fence,
+    ) -> Result<()> {
+        let command_buffers = [self.command_buffer];
+        let wait_stages = vec![vk::PipelineStageFlags::ALL_COMMANDS; wait_semaphores.len()];
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&command_buffers)
+            .signal_semaphores(signal_semaphores);
+        device.queue_submit(self.queue, &[submit_info.build()], fence)
+    }
+}
+
+/// An upload that has been submitted to the GPU.
+/// Dropping it waits for the upload to finish so its staging memory can be released.
+pub struct PendingUpload {
+    fence: Arc<Fence>,
+    _semaphore: Option<Semaphore>,
+    _commands: Vec<BatchCommands>,
+    _staging: StagingLease,
+    _retained: Vec<Box<dyn Send>>,
+    device: Arc<Device>,
+}
+
+impl PendingUpload {
+    pub fn wait(&self) -> Result<()> {
+        unsafe {
+            self.device
+                .handle
+                .wait_for_fences(&[self.fence.handle], true, std::u64::MAX)
+        }?;
+        self.device.collect_completed_garbage();
+        Ok(())
+    }
+
+    pub fn is_complete(&self) -> Result<bool> {
+        let complete = unsafe { self.device.handle.get_fence_status(self.fence.handle) }?;
+        if complete {
+            self.device.collect_completed_garbage();
+        }
+        Ok(complete)
+    }
+}
+
+impl Drop for PendingUpload {
+    fn drop(&mut self) {
+        if let Err(error) = self.wait() {
+            log::error!("Failed to wait for pending upload: {}", error);
+        }
+    }
+}
+
+/// A resource whose contents are still being uploaded.
+/// The render loop can poll it each frame and start using the resource once it is ready.
+pub struct AsyncUpload<T> {
+    // Declared first so that dropping an unfinished upload waits before the resource is destroyed
+    pending: PendingUpload,
+    resource: Option<T>,
+}
+
+impl<T> AsyncUpload<T> {
+    pub fn new(resource: T, pending: PendingUpload) -> Self {
+        Self {
+            pending,
+            resource: Some(resource),
+        }
+    }
+
+    pub fn is_complete(&self) -> Result<bool> {
+        self.pending.is_complete()
+    }
+
+    /// Takes the resource if the upload has finished, without blocking
+    pub fn poll(&mut self) -> Result<Option<T>> {
+        if self.resource.is_some() && self.pending.is_complete()? {
+            Ok(self.resource.take())
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn wait(mut self) -> Result<T> {
+        self.pending.wait()?;
+        self.resource
+            .take()
+            .context("The uploaded resource was already taken!")
+    }
+}
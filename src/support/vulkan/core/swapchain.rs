@@ -1,6 +1,7 @@
 use crate::vulkan::core::context::{Context, Surface};
 use anyhow::{ensure, Result};
 use ash::{extensions::khr::Swapchain as AshSwapchain, vk};
+use derive_builder::Builder;
 use std::cmp;
 
 pub struct Swapchain {
@@ -48,11 +49,115 @@ impl Drop for Swapchain {
     }
 }
 
+#[derive(Builder, Clone, Debug)]
+#[builder(default, setter(into))]
+pub struct SwapchainConfig {
+    /// Present modes in order of preference. FIFO is used if none of them are available.
+    pub present_modes: Vec<vk::PresentModeKHR>,
+
+    /// Surface formats in order of preference. The first format
+    /// reported by the surface is used if none of them are available.
+    pub surface_formats: Vec<vk::SurfaceFormatKHR>,
+
+    /// Clamped to the surface capabilities. Defaults to one more than the minimum image count.
+    pub image_count: Option<u32>,
+}
+
+impl SwapchainConfig {
+    pub const VSYNC: &'static [vk::PresentModeKHR] = &[vk::PresentModeKHR::FIFO];
+
+    pub const RELAXED_VSYNC: &'static [vk::PresentModeKHR] =
+        &[vk::PresentModeKHR::FIFO_RELAXED, vk::PresentModeKHR::FIFO];
+
+    pub const LOW_LATENCY: &'static [vk::PresentModeKHR] =
+        &[vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::FIFO];
+
+    pub const NO_VSYNC: &'static [vk::PresentModeKHR] = &[
+        vk::PresentModeKHR::IMMEDIATE,
+        vk::PresentModeKHR::MAILBOX,
+        vk::PresentModeKHR::FIFO,
+    ];
+
+    pub const UNORM_FORMATS: &'static [vk::SurfaceFormatKHR] = &[
+        vk::SurfaceFormatKHR {
+            format: vk::Format::R8G8B8A8_UNORM,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        },
+        vk::SurfaceFormatKHR {
+            format: vk::Format::B8G8R8A8_UNORM,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        },
+    ];
+
+    pub const SRGB_FORMATS: &'static [vk::SurfaceFormatKHR] = &[
+        vk::SurfaceFormatKHR {
+            format: vk::Format::B8G8R8A8_SRGB,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        },
+        vk::SurfaceFormatKHR {
+            format: vk::Format::R8G8B8A8_SRGB,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        },
+    ];
+
+    /// Requires VK_EXT_swapchain_colorspace, which is enabled when available
+    pub const HDR10_FORMATS: &'static [vk::SurfaceFormatKHR] = &[
+        vk::SurfaceFormatKHR {
+            format: vk::Format::A2B10G10R10_UNORM_PACK32,
+            color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+        },
+        vk::SurfaceFormatKHR {
+            format: vk::Format::A2R10G10B10_UNORM_PACK32,
+            color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+        },
+    ];
+
+    /// Requires VK_EXT_swapchain_colorspace, which is enabled when available
+    pub const SCRGB_FORMATS: &'static [vk::SurfaceFormatKHR] = &[vk::SurfaceFormatKHR {
+        format: vk::Format::R16G16B16A16_SFLOAT,
+        color_space: vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+    }];
+
+    /// Prefers HDR10, then scRGB, falling back to SDR when the display does not support HDR
+    pub fn hdr() -> Self {
+        let surface_formats = [
+            Self::HDR10_FORMATS,
+            Self::SCRGB_FORMATS,
+            Self::UNORM_FORMATS,
+        ]
+        .concat();
+        Self {
+            surface_formats,
+            ..Default::default()
+        }
+    }
+
+    pub fn vsync(enabled: bool) -> Self {
+        let present_modes = if enabled { Self::VSYNC } else { Self::NO_VSYNC };
+        Self {
+            present_modes: present_modes.to_vec(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for SwapchainConfig {
+    fn default() -> Self {
+        Self {
+            present_modes: Self::LOW_LATENCY.to_vec(),
+            surface_formats: Self::UNORM_FORMATS.to_vec(),
+            image_count: None,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct SwapchainProperties {
     pub surface_format: vk::SurfaceFormatKHR,
     pub present_mode: vk::PresentModeKHR,
     pub extent: vk::Extent2D,
+    /// Swapchain images can also be copied from when the surface supports it
+    pub image_usage: vk::ImageUsageFlags,
 }
 
 impl SwapchainProperties {
@@ -60,14 +165,17 @@ impl SwapchainProperties {
         dimensions: &[u32; 2],
         device: vk::PhysicalDevice,
         surface: &Surface,
+        config: &SwapchainConfig,
     ) -> Result<Self> {
         let extent = Self::select_extent(dimensions, device, surface)?;
-        let surface_format = Self::select_format(device, surface)?;
-        let present_mode = Self::select_present_mode(device, surface)?;
+        let surface_format = Self::select_format(device, surface, &config.surface_formats)?;
+        let present_mode = Self::select_present_mode(device, surface, &config.present_modes)?;
+        let image_usage = Self::select_image_usage(device, surface)?;
         let properties = Self {
             surface_format,
             present_mode,
             extent,
+            image_usage,
         };
         Ok(properties)
     }
@@ -95,9 +203,29 @@ impl SwapchainProperties {
         }
     }
 
+    fn select_image_usage(
+        device: vk::PhysicalDevice,
+        surface: &Surface,
+    ) -> Result<vk::ImageUsageFlags> {
+        let capabilities = unsafe {
+            surface
+                .handle_ash
+                .get_physical_device_surface_capabilities(device, surface.handle_khr)
+        }?;
+        let mut usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+        if capabilities
+            .supported_usage_flags
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        }
+        Ok(usage)
+    }
+
     fn select_format(
         device: vk::PhysicalDevice,
         surface: &Surface,
+        preferred_formats: &[vk::SurfaceFormatKHR],
     ) -> Result<vk::SurfaceFormatKHR> {
         let formats = unsafe {
             surface
@@ -108,27 +236,27 @@ impl SwapchainProperties {
         let error_message = "No physical device surface formats are available!";
         ensure!(!formats.is_empty(), error_message);
 
-        let default_format = vk::SurfaceFormatKHR {
-            format: vk::Format::R8G8B8A8_UNORM,
-            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
-        };
-
         let all_formats_undefined = formats
             .iter()
             .all(|format| format.format == vk::Format::UNDEFINED);
 
-        let default_available = formats.contains(&default_format);
-
-        if default_available || all_formats_undefined {
-            Ok(default_format)
+        // When every format is undefined the surface places no restrictions on the format
+        let format = if all_formats_undefined {
+            preferred_formats.first().copied()
         } else {
-            Ok(formats[0])
-        }
+            preferred_formats
+                .iter()
+                .find(|format| formats.contains(format))
+                .copied()
+        };
+
+        Ok(format.unwrap_or(formats[0]))
     }
 
     fn select_present_mode(
         device: vk::PhysicalDevice,
         surface: &Surface,
+        preferred_present_modes: &[vk::PresentModeKHR],
     ) -> Result<vk::PresentModeKHR> {
         let present_modes = unsafe {
             surface
@@ -136,11 +264,12 @@ impl SwapchainProperties {
                 .get_physical_device_surface_present_modes(device, surface.handle_khr)
         }?;
 
-        let present_mode = match present_modes.as_slice() {
-            [vk::PresentModeKHR::MAILBOX, ..] => vk::PresentModeKHR::MAILBOX,
-            [vk::PresentModeKHR::FIFO, ..] => vk::PresentModeKHR::FIFO,
-            _ => vk::PresentModeKHR::IMMEDIATE,
-        };
+        // FIFO is the only present mode that is required to be supported
+        let present_mode = preferred_present_modes
+            .iter()
+            .find(|present_mode| present_modes.contains(present_mode))
+            .copied()
+            .unwrap_or(vk::PresentModeKHR::FIFO);
 
         Ok(present_mode)
     }
@@ -148,20 +277,104 @@ impl SwapchainProperties {
     pub fn aspect_ratio(&self) -> f32 {
         self.extent.width as f32 / cmp::max(self.extent.height, 1) as f32
     }
+
+    pub fn output_color_space(&self) -> OutputColorSpace {
+        OutputColorSpace::from(self.surface_format.color_space)
+    }
+}
+
+/// The color space the final output transform has to encode for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputColorSpace {
+    Sdr = 0,
+    Hdr10 = 1,
+    ScRgb = 2,
 }
 
+impl OutputColorSpace {
+    pub fn is_hdr(&self) -> bool {
+        *self != Self::Sdr
+    }
+}
+
+impl From<vk::ColorSpaceKHR> for OutputColorSpace {
+    fn from(color_space: vk::ColorSpaceKHR) -> Self {
+        match color_space {
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => Self::Hdr10,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => Self::ScRgb,
+            _ => Self::Sdr,
+        }
+    }
+}
+
+/// Mastering display and content light levels, in chromaticity coordinates and nits
+#[derive(Clone, Copy, Debug)]
+pub struct HdrMetadata {
+    pub display_primary_red: [f32; 2],
+    pub display_primary_green: [f32; 2],
+    pub display_primary_blue: [f32; 2],
+    pub white_point: [f32; 2],
+    pub max_luminance: f32,
+    pub min_luminance: f32,
+    pub max_content_light_level: f32,
+    pub max_frame_average_light_level: f32,
+}
+
+impl HdrMetadata {
+    pub fn to_vk(&self) -> vk::HdrMetadataEXT {
+        let xy = |[x, y]: [f32; 2]| vk::XYColorEXT { x, y };
+        vk::HdrMetadataEXT::builder()
+            .display_primary_red(xy(self.display_primary_red))
+            .display_primary_green(xy(self.display_primary_green))
+            .display_primary_blue(xy(self.display_primary_blue))
+            .white_point(xy(self.white_point))
+            .max_luminance(self.max_luminance)
+            .min_luminance(self.min_luminance)
+            .max_content_light_level(self.max_content_light_level)
+            .max_frame_average_light_level(self.max_frame_average_light_level)
+            .build()
+    }
+}
+
+impl Default for HdrMetadata {
+    /// Rec. 2020 primaries with a D65 white point, mastered at 1000 nits
+    fn default() -> Self {
+        Self {
+            display_primary_red: [0.708, 0.292],
+            display_primary_green: [0.170, 0.797],
+            display_primary_blue: [0.131, 0.046],
+            white_point: [0.3127, 0.3290],
+            max_luminance: 1000.0,
+            min_luminance: 0.001,
+            max_content_light_level: 1000.0,
+            max_frame_average_light_level: 400.0,
+        }
+    }
+}
+
+/// Passing the swapchain being replaced lets the driver reuse its resources.
+/// The old swapchain is retired and must be kept alive until
+/// the frames that were using it have finished executing.
 pub fn create_swapchain(
     context: &Context,
     dimensions: &[u32; 2],
+    config: &SwapchainConfig,
+    old_swapchain: Option<&Swapchain>,
 ) -> Result<(Swapchain, SwapchainProperties)> {
     let properties = SwapchainProperties::new(
         dimensions,
         context.physical_device.handle,
         context.surface()?,
+        config,
     )?;
 
     let queue_indices = context.physical_device.queue_indices();
-    let create_info = swapchain_create_info(context, &queue_indices, properties)?;
+    let create_info = swapchain_create_info(context, &queue_indices, properties, config)?
+        .old_swapchain(
+            old_swapchain
+                .map(|swapchain| swapchain.handle_khr)
+                .unwrap_or_else(vk::SwapchainKHR::null),
+        );
 
     let swapchain = Swapchain::new(
         &context.instance.handle,
@@ -176,12 +389,10 @@ fn swapchain_create_info<'a>(
     context: &Context,
     queue_indices: &'a [u32],
     properties: SwapchainProperties,
+    config: &SwapchainConfig,
 ) -> Result<vk::SwapchainCreateInfoKHRBuilder<'a>> {
     let capabilities = context.physical_device_surface_capabilities()?;
-    let image_count = std::cmp::max(
-        capabilities.max_image_count,
-        capabilities.min_image_count + 1,
-    );
+    let image_count = select_image_count(&capabilities, config.image_count);
     let builder = vk::SwapchainCreateInfoKHR::builder()
         .surface(context.surface()?.handle_khr)
         .min_image_count(image_count)
@@ -189,7 +400,7 @@ fn swapchain_create_info<'a>(
         .image_color_space(properties.surface_format.color_space)
         .image_extent(properties.extent)
         .image_array_layers(1)
-        .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+        .image_usage(properties.image_usage)
         .pre_transform(capabilities.current_transform)
         .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
         .present_mode(properties.present_mode)
@@ -206,3 +417,16 @@ fn swapchain_create_info<'a>(
 
     Ok(builder)
 }
+
+fn select_image_count(capabilities: &vk::SurfaceCapabilitiesKHR, desired: Option<u32>) -> u32 {
+    let image_count = desired
+        .unwrap_or(capabilities.min_image_count + 1)
+        .max(capabilities.min_image_count);
+
+    // A maximum image count of zero means there is no limit
+    if capabilities.max_image_count > 0 {
+        image_count.min(capabilities.max_image_count)
+    } else {
+        image_count
+    }
+}
@@ -1,20 +1,22 @@
 use crate::vulkan::{
     byte_slice_from,
     core::{
-        CommandPool, DescriptorSetLayout, Device, GeometryBuffer, GraphicsPipelineSettingsBuilder,
-        Pipeline, PipelineLayout, RenderPass, ShaderCache, ShaderPathSet, ShaderPathSetBuilder,
+        Context, DescriptorSetLayout, Device, GeometryBuffer, GraphicsPipelineSettingsBuilder,
+        OutputColorSpace, Pipeline, PipelineLayout, RenderPass, ShaderCache, ShaderPathSet,
+        ShaderPathSetBuilder,
     },
 };
 use anyhow::{anyhow, Context as AnyhowContext, Result};
 use ash::{version::DeviceV1_0, vk};
 use nalgebra_glm as glm;
 use std::sync::Arc;
-use vk_mem::Allocator;
 
+#[repr(C)]
 #[derive(Debug)]
 pub struct CubePushConstantBlock {
     pub mvp: glm::Mat4,
     pub color: glm::Vec4,
+    pub output_color_space: u32,
 }
 
 pub struct CubeRender {
@@ -137,6 +139,7 @@ impl CubeRender {
         mvp: glm::Mat4,
         color: glm::Vec4,
         solid: bool,
+        output_color_space: OutputColorSpace,
     ) -> Result<()> {
         let solid_pipeline = self
             .solid_pipeline
@@ -153,7 +156,12 @@ impl CubeRender {
             .as_ref()
             .context("Failed to get pipeline layout for rendering asset!")?;
 
-        let push_constants = CubePushConstantBlock { mvp, color };
+        // The cube is drawn straight into the swapchain image, so it encodes for its color space
+        let push_constants = CubePushConstantBlock {
+            mvp,
+            color,
+            output_color_space: output_color_space as u32,
+        };
         unsafe {
             self.device.handle.cmd_push_constants(
                 command_buffer,
@@ -239,22 +247,23 @@ pub struct Cube {
 }
 
 impl Cube {
-    pub fn new(allocator: Arc<Allocator>, command_pool: &CommandPool) -> Result<Self> {
+    pub fn new(context: &Context) -> Result<Self> {
         let geometry_buffer = GeometryBuffer::new(
-            allocator,
+            context.device.clone(),
+            context.allocator.clone(),
             (VERTICES.len() * std::mem::size_of::<f32>()) as _,
             Some((INDICES.len() * std::mem::size_of::<u32>()) as _),
         )?;
 
         geometry_buffer
             .vertex_buffer
-            .upload_data(VERTICES, 0, command_pool)?;
+            .upload_data(context, VERTICES, 0)?;
 
         geometry_buffer
             .index_buffer
             .as_ref()
             .context("Failed to access cube index buffer!")?
-            .upload_data(INDICES, 0, command_pool)?;
+            .upload_data(context, INDICES, 0)?;
 
         Ok(Self { geometry_buffer })
     }
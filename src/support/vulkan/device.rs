@@ -3,7 +3,7 @@ use ash::vk;
 use raw_window_handle::HasRawWindowHandle;
 use std::sync::Arc;
 
-use super::core::{CommandPool, Context, Frame};
+use super::core::{CommandPool, Context, Frame, FrameConfig, StagingConfig, SwapchainConfig};
 
 pub struct RenderDevice {
     pub command_pool: CommandPool,
@@ -14,9 +14,21 @@ pub struct RenderDevice {
 impl RenderDevice {
     const MAX_FRAMES_IN_FLIGHT: usize = 2;
 
-    pub fn new(window_handle: &impl HasRawWindowHandle, dimensions: &[u32; 2]) -> Result<Self> {
-        let context = Arc::new(Context::new(window_handle)?);
-        let frame = Frame::new(context.clone(), dimensions, Self::MAX_FRAMES_IN_FLIGHT)?;
+    pub fn new(
+        window_handle: &impl HasRawWindowHandle,
+        dimensions: &[u32; 2],
+        swapchain_config: SwapchainConfig,
+        staging_config: StagingConfig,
+        frame_config: FrameConfig,
+    ) -> Result<Self> {
+        let context = Arc::new(Context::new(window_handle, &staging_config)?);
+        let frame = Frame::new(
+            context.clone(),
+            dimensions,
+            Self::MAX_FRAMES_IN_FLIGHT,
+            swapchain_config,
+            &frame_config,
+        )?;
 
         let create_info = vk::CommandPoolCreateInfo::builder()
             .queue_family_index(context.physical_device.graphics_queue_family_index)
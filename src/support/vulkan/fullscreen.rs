@@ -1,12 +1,21 @@
-use crate::vulkan::core::{
-    Context, DescriptorPool, DescriptorSetLayout, Device, GraphicsPipelineSettings,
-    GraphicsPipelineSettingsBuilder, Pipeline, PipelineLayout, RenderPass, ShaderCache,
-    ShaderPathSet,
+use crate::vulkan::{
+    byte_slice_from,
+    core::{
+        Context, DescriptorPool, DescriptorSetLayout, Device, GraphicsPipelineSettings,
+        GraphicsPipelineSettingsBuilder, OutputColorSpace, Pipeline, PipelineLayout, RenderPass,
+        ShaderCache, ShaderPathSet,
+    },
 };
 use anyhow::{anyhow, Context as AnyhowContext, Result};
 use ash::{version::DeviceV1_0, vk};
 use std::sync::Arc;
 
+#[repr(C)]
+#[derive(Debug)]
+pub struct FullscreenPushConstantBlock {
+    pub output_color_space: u32,
+}
+
 pub struct FullscreenRender {
     pub pipeline: Option<Pipeline>,
     pub pipeline_layout: PipelineLayout,
@@ -58,12 +67,17 @@ impl FullscreenRender {
         shader_paths: ShaderPathSet,
     ) -> Result<GraphicsPipelineSettings> {
         let shader_set = shader_cache.create_shader_set(device, &shader_paths)?;
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .size(std::mem::size_of::<FullscreenPushConstantBlock>() as u32)
+            .build();
         let settings = GraphicsPipelineSettingsBuilder::default()
             .shader_set(shader_set)
             .render_pass(render_pass)
             .vertex_inputs(Vec::new())
             .vertex_attributes(Vec::new())
             .descriptor_set_layout(descriptor_set_layout)
+            .push_constant_range(push_constant_range)
             .dynamic_states(vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
             .build()
             .map_err(|error| anyhow!("{}", error))?;
@@ -115,14 +129,33 @@ impl FullscreenRender {
         unsafe { self.device.handle.update_descriptor_sets(writes, &[]) }
     }
 
-    pub fn issue_commands(&self, command_buffer: vk::CommandBuffer) -> Result<()> {
+    /// The color space should be read from the swapchain every frame,
+    /// since it can change whenever the swapchain is recreated
+    pub fn issue_commands(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        output_color_space: OutputColorSpace,
+    ) -> Result<()> {
         let pipeline = self
             .pipeline
             .as_ref()
             .context("Failed to get fullscreen pipeline!")?;
         pipeline.bind(&self.device.handle, command_buffer);
 
+        // The output transform encodes for the color space of the swapchain being written to
+        let push_constants = FullscreenPushConstantBlock {
+            output_color_space: output_color_space as u32,
+        };
+
         unsafe {
+            self.device.handle.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout.handle,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                byte_slice_from(&push_constants),
+            );
+
             self.device.handle.cmd_bind_descriptor_sets(
                 command_buffer,
                 vk::PipelineBindPoint::GRAPHICS,